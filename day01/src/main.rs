@@ -30,13 +30,13 @@ fn fuel_cost(mass: usize) -> usize {
 }
 
 fn part1(input: &[usize]) -> usize {
-    input.into_iter().map(|el| fuel_cost(*el)).sum::<usize>()
+    input.iter().map(|el| fuel_cost(*el)).sum::<usize>()
 }
 
 fn part2(input: &[usize]) -> usize {
     let mut cache = BTreeMap::new();
     input
-        .into_iter()
+        .iter()
         .map(|el| memoized(&mut cache, *el))
         .sum::<usize>()
 }
@@ -1,7 +1,6 @@
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs;
-use std::io::{self, prelude::*};
 use std::ops::Add;
 use std::path::Path;
 use std::str::FromStr;
@@ -188,9 +187,7 @@ fn intersects_steps(a: &[Line], b: &[Line]) -> HashMap<Point, usize> {
 
                 let _sa = a_line.a.dist(pt) + a_steps;
                 let _sb = b_line.a.dist(pt) + b_steps;
-                if !pts.contains_key(&pt) {
-                    pts.insert(pt, _sa + _sb);
-                }
+                pts.entry(pt).or_insert(_sa + _sb);
             }
             b_steps += b_line.b.dist(b_pt);
             b_pt = b_line.b;
@@ -1,6 +1,5 @@
 use std::collections::HashMap;
 use std::fs;
-use std::io::{self, prelude::*};
 use std::path::Path;
 
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
@@ -50,16 +49,7 @@ impl Graph {
         self.parent.get(self.nodes.get(k)?).map(|_| 1)
     }
 
-    fn to_name(&self, index: usize) -> Option<&str> {
-        for (s, &i) in &self.nodes {
-            if i == index {
-                return Some(s.as_ref());
-            }
-        }
-        None
-    }
-
-    pub fn iter(&self, node: &str) -> Option<IndOrbitIter> {
+    pub fn iter(&self, node: &str) -> Option<IndOrbitIter<'_>> {
         Some(IndOrbitIter {
             graph: self,
             ptr: *self.parent.get(self.nodes.get(node)?)?,
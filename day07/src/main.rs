@@ -1,4 +1,4 @@
-use intcode::Vm;
+use intcode::{Scheduler, Vm};
 use std::iter::Iterator;
 
 struct Iter {
@@ -7,16 +7,6 @@ struct Iter {
     state: isize,
 }
 
-impl Iter {
-    pub fn new(phase: isize, input: isize) -> Self {
-        Iter {
-            phase,
-            input,
-            state: 0,
-        }
-    }
-}
-
 impl Iterator for Iter {
     type Item = isize;
     fn next(&mut self) -> Option<Self::Item> {
@@ -31,7 +21,7 @@ impl Iterator for Iter {
 }
 
 fn phrase(vm: &Vm, phrase: &[isize]) -> isize {
-    phrase.into_iter().fold(0, |acc, ph| {
+    phrase.iter().fold(0, |acc, ph| {
         vm.clone()
             .run(
                 Iter {
@@ -47,7 +37,7 @@ fn phrase(vm: &Vm, phrase: &[isize]) -> isize {
 
 fn heaps(slice: &mut [isize], n: usize, out: &mut Vec<Vec<isize>>) {
     if n == 1 {
-        out.push(slice.iter().copied().collect());
+        out.push(slice.to_vec());
     } else {
         for i in 0..n {
             heaps(slice, n - 1, out);
@@ -75,33 +65,24 @@ fn part1(input: &str) -> Option<isize> {
     Some(max)
 }
 
-fn run_loop(mut vms: Vec<Vm>, phase: &[isize]) -> isize {
-    let mut acc = vms.iter_mut().zip(phase).fold(0, |acc, (vm, ph)| {
-        vm.run(Iter::new(*ph, acc), false).unwrap()
-    });
-
-    'outer: loop {
-        for i in 0..5 {
-            match vms[i].run(std::iter::once(acc), false) {
-                Ok(x) => {
-                    acc = x;
-                    // continue;
-                }
-                Err(e) => {
-                    if i == 4 {
-                        break 'outer;
-                    }
-                }
-            }
-        }
+fn run_loop(vms: Vec<Vm>, phase: &[isize]) -> isize {
+    let n = vms.len();
+    let mut scheduler = Scheduler::new(1000);
+    for (vm, &ph) in vms.into_iter().zip(phase) {
+        let id = scheduler.add(vm);
+        scheduler.send(id, ph);
+        scheduler.connect(id, (id + 1) % n);
     }
+    scheduler.send(0, 0);
+    scheduler.run().unwrap();
 
-    acc
+    // The last amplifier's final signal is left queued for the first one
+    *scheduler.pending(0).back().unwrap()
 }
 
 fn part2(input: &str) -> Option<isize> {
     let vm = input.parse::<Vm>().ok()?;
-    let vms = std::iter::repeat(vm.clone()).take(5).collect::<Vec<_>>();
+    let vms = vec![vm; 5];
 
     let mut max = 0;
 
@@ -158,42 +139,25 @@ fn examples_part1() {
 
 #[test]
 fn examples_part2() {
-    fn harness(input: &str, phrase: &[isize]) -> Option<isize> {
-        let mut vm = input.parse::<Vm>().ok()?;
-        let mut max = 0;
-        let mut x = 0;
-
-        'outer: loop {
-            for ph in phrase {
-                x = match vm.run(
-                    Iter {
-                        phase: *ph,
-                        input: x,
-                        state: 0,
-                    },
-                    true,
-                ) {
-                    Ok(x) => dbg!(x),
-                    Err(e) => {
-                        println!("fail {:?}", &phrase);
-                        break 'outer;
-                    }
-                };
-                if x > max {
-                    max = x;
-                }
-            }
-        }
-
-        Some(max)
-    }
-
-    // assert_eq!(
-    //     harness(
-    //         &"3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,
-    //         27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
-    //         &[9, 8, 7, 6, 5]
-    //     ),
-    //     Some(139629729)
-    // );
+    let run = |input: &str, phase: &[isize]| {
+        let vm = input.parse::<Vm>().unwrap();
+        run_loop(vec![vm; 5], phase)
+    };
+    assert_eq!(
+        run(
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,
+            27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+            &[9, 8, 7, 6, 5]
+        ),
+        139629729
+    );
+    assert_eq!(
+        run(
+            "3,52,1001,52,-5,52,3,53,1,52,56,54,1007,54,5,55,1005,55,26,1001,54,
+            -5,54,1105,1,12,1,53,54,53,1008,54,0,55,1001,55,1,55,2,53,55,53,4,
+            53,1001,56,-1,56,1005,56,6,99,0,0,0,0,10",
+            &[9, 7, 8, 5, 6]
+        ),
+        18216
+    );
 }
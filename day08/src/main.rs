@@ -3,7 +3,7 @@ fn parse(input: &str) -> Vec<u8> {
         .chars()
         .filter_map(|ch| {
             if ch.is_ascii_digit() {
-                Some(ch as u8 - '0' as u8)
+                Some(ch as u8 - b'0')
             } else {
                 None
             }
@@ -28,7 +28,7 @@ fn part1(size: usize, data: &[u8]) -> Option<usize> {
 
 fn part2(w: usize, h: usize, data: &[u8]) -> Vec<String> {
     let images = data.chunks_exact(w * h).collect::<Vec<_>>();
-    let mut output = std::iter::repeat(0).take(w * h).collect::<Vec<_>>();
+    let mut output = vec![0; w * h];
     for layer in images.iter().rev() {
         for (idx, pixel) in layer.iter().enumerate() {
             output[idx] = match pixel {
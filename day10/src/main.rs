@@ -1,7 +1,8 @@
 use grid::{Grid, Point};
 use std::collections::HashMap;
+use std::convert::Infallible;
 
-#[derive(Copy, Clone, PartialEq, PartialOrd, Eq, Hash, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 enum Slope {
     Vertical(bool),
     Horizontal(bool),
@@ -35,6 +36,12 @@ impl Slope {
 }
 
 use std::cmp::Ordering;
+impl std::cmp::PartialOrd for Slope {
+    fn partial_cmp(&self, other: &Slope) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl std::cmp::Ord for Slope {
     fn cmp(&self, other: &Slope) -> Ordering {
         match self.rotation().cmp(&other.rotation()) {
@@ -121,28 +128,20 @@ fn part2(grid: &Grid<Space>, base: Point) -> usize {
         });
         reachable.push((s, nearest.1));
     }
-    reachable.sort_by(|a, b| a.0.cmp(&b.0));
+    reachable.sort_by_key(|a| a.0);
 
     let bet = reachable[199].1;
     bet.x * 100 + bet.y
 }
 
 fn parse(input: &str) -> Grid<Space> {
-    let mut cols = 0;
-    let mut rows = 0;
-    let mut grid = Vec::new();
-    for line in input.lines() {
-        cols = 0;
-        for ch in line.chars() {
-            match ch {
-                '.' => grid.push(Space::Empty),
-                _ => grid.push(Space::Asteroid),
-            }
-            cols += 1;
-        }
-        rows += 1;
-    }
-    Grid::new(cols, rows, grid)
+    Grid::parse(input, |ch| {
+        Ok::<_, Infallible>(match ch {
+            '.' => Space::Empty,
+            _ => Space::Asteroid,
+        })
+    })
+    .unwrap()
 }
 
 fn main() {
@@ -1,7 +1,6 @@
-use grid::{Grid, Point};
+use grid::{BoundingBox, Coord, Direction, Grid, Rotation};
 use intcode::Vm;
-use std::collections::{HashMap, HashSet};
-use std::convert::TryFrom;
+use std::collections::HashMap;
 use std::iter::Iterator;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -19,68 +18,6 @@ impl Color {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum Rotation {
-    Left,
-    Right,
-}
-
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum Direction {
-    Up,
-    Down,
-    Left,
-    Right,
-}
-
-impl Direction {
-    pub fn rotate(self, rotation: Rotation) -> Direction {
-        match rotation {
-            Rotation::Left => match self {
-                Direction::Up => Direction::Left,
-                Direction::Down => Direction::Right,
-                Direction::Left => Direction::Down,
-                Direction::Right => Direction::Up,
-            },
-            Rotation::Right => match self {
-                Direction::Up => Direction::Right,
-                Direction::Down => Direction::Left,
-                Direction::Left => Direction::Up,
-                Direction::Right => Direction::Down,
-            },
-        }
-    }
-}
-
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
-struct Coord {
-    x: isize,
-    y: isize,
-}
-
-impl Coord {
-    pub fn step(self, dir: Direction) -> Coord {
-        match dir {
-            Direction::Up => Coord {
-                x: self.x,
-                y: self.y - 1,
-            },
-            Direction::Down => Coord {
-                x: self.x,
-                y: self.y + 1,
-            },
-            Direction::Left => Coord {
-                x: self.x - 1,
-                y: self.y,
-            },
-            Direction::Right => Coord {
-                x: self.x + 1,
-                y: self.y,
-            },
-        }
-    }
-}
-
 fn step(vm: &mut Vm, input: Color) -> Option<(Color, Rotation)> {
     let c = match vm.run(std::iter::once(input.value()), false).ok()? {
         0 => Color::Black,
@@ -96,33 +33,18 @@ fn step(vm: &mut Vm, input: Color) -> Option<(Color, Rotation)> {
 }
 
 fn pretty_print(last: Coord, colors: HashMap<Coord, Color>, dir: Direction) {
-    let min_x = colors.keys().map(|c| c.x).min().unwrap();
-    let min_y = colors.keys().map(|c| c.y).min().unwrap();
-    let max_x = colors.keys().map(|c| c.x).max().unwrap();
-    let max_y = colors.keys().map(|c| c.y).max().unwrap();
-
-    let cols = usize::try_from(max_x - min_x).unwrap() + 1;
-    let rows = usize::try_from(max_y - min_y).unwrap() + 1;
-    let g = std::iter::repeat('.')
-        .take(cols * rows)
-        .collect::<Vec<char>>();
-    let mut grid = Grid::new(cols, rows, g);
+    // The robot may have stepped past the last panel it painted
+    let bounds = BoundingBox::from_points(colors.keys().copied().chain(Some(last))).unwrap();
+    let (cols, rows) = (bounds.width(), bounds.height());
+    let mut grid = Grid::new(cols, rows, vec!['.'; cols * rows]);
 
     for (coord, color) in colors {
-        let pt = Point::new(
-            (coord.x + min_x.abs()) as usize,
-            (coord.y + min_y.abs()) as usize,
-        );
-        grid[pt] = match color {
+        grid[bounds.to_point(coord).unwrap()] = match color {
             Color::Black => '.',
             Color::White => '#',
         };
     }
-    let pt = Point::new(
-        (last.x + min_x.abs()) as usize,
-        (last.y + min_y.abs()) as usize,
-    );
-    grid[pt] = match dir {
+    grid[bounds.to_point(last).unwrap()] = match dir {
         Direction::Up => '^',
         Direction::Down => 'v',
         Direction::Left => '<',
@@ -134,13 +56,13 @@ fn pretty_print(last: Coord, colors: HashMap<Coord, Color>, dir: Direction) {
 fn problem(mut vm: Vm, initial_color: Color, print_grid: bool) -> usize {
     let mut colors = HashMap::new();
     let mut dir = Direction::Up;
-    let mut position = Coord { x: 0, y: 0 };
+    let mut position = Coord::new(0, 0);
     let mut on = initial_color;
 
     while let Some((c, r)) = step(&mut vm, on) {
         colors.insert(position, c);
         dir = dir.rotate(r);
-        position = position.step(dir);
+        position = position.move_one(dir);
         on = *colors.get(&position).unwrap_or(&Color::Black);
     }
     let n = colors.len();
@@ -1,7 +1,6 @@
 use std::collections::HashSet;
 use std::error::Error;
 use std::iter::Iterator;
-use std::usize;
 
 /// That's no moon!
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
@@ -110,7 +109,7 @@ fn lcm(x: usize, y: usize) -> usize {
     x * y / gcd(x, y)
 }
 
-fn part2(moons: &mut Vec<Moon>) -> usize {
+fn part2(moons: &mut [Moon]) -> usize {
     let mut x = HashSet::new();
     let mut y = HashSet::new();
     let mut z = HashSet::new();
@@ -181,7 +180,7 @@ fn parse(input: &str) -> Result<Vec<Moon>, std::num::ParseIntError> {
                     .collect::<String>()
             })
         })
-        .filter(|s| s.len() > 0)
+        .filter(|s| !s.is_empty())
         .map(|v| v.parse::<isize>())
         .collect::<Result<Vec<_>, _>>()?;
 
@@ -1,6 +1,5 @@
-use grid::{Coord, Grid};
-use intcode::Vm;
-use std::collections::HashMap;
+use grid::{Coord, SparseGrid};
+use intcode::{InputLog, Player, Vm};
 use std::iter::Iterator;
 
 fn part1(mut vm: Vm) -> usize {
@@ -35,7 +34,17 @@ impl Iterator for Iter {
     }
 }
 
-fn part2(mut vm: Vm, animate: bool) -> isize {
+/// Where the joystick moves of part 2 come from
+enum Joystick<'a> {
+    /// Keep the paddle under the ball
+    Ai,
+    /// Keep the paddle under the ball, logging every move
+    Record(&'a mut InputLog),
+    /// Replay the moves of a saved session
+    Replay(Player<'a>),
+}
+
+fn part2(mut vm: Vm, animate: bool, mut joystick: Joystick) -> isize {
     vm.data[0] = 2;
     let mut ball = Coord::default();
     let mut paddle = Coord::default();
@@ -43,14 +52,14 @@ fn part2(mut vm: Vm, animate: bool) -> isize {
 
     let mut out = Vec::new();
 
-    let mut field = HashMap::new();
+    let mut field = SparseGrid::new(0).background('0');
     loop {
         for slice in out.chunks_exact(3) {
             match slice[2] {
                 3 => paddle = Coord::new(slice[0], slice[1]),
                 4 => ball = Coord::new(slice[0], slice[1]),
                 x if (slice[0] == -1 && slice[1] == 0) => score = x,
-                x => {
+                _ => {
                     field.insert(Coord::new(slice[0], slice[1]), slice[2]);
                 }
             }
@@ -60,7 +69,12 @@ fn part2(mut vm: Vm, animate: bool) -> isize {
             out.clear();
         }
 
-        match vm.run(Iter { ball, paddle }, false) {
+        let result = match &mut joystick {
+            Joystick::Ai => vm.run(Iter { ball, paddle }, false),
+            Joystick::Record(log) => vm.run(log.record(Iter { ball, paddle }), false),
+            Joystick::Replay(moves) => vm.run(moves, false),
+        };
+        match result {
             Ok(r) => out.push(r),
             Err(intcode::Error::Halted) => {
                 break;
@@ -74,18 +88,33 @@ fn part2(mut vm: Vm, animate: bool) -> isize {
         if animate && paddle != Coord::default() {
             field.insert(paddle, 3);
             field.insert(ball, 4);
-            let g = Coord::to_grid(field.clone());
-            field.remove(&paddle);
-            field.remove(&ball);
-            println!("\n\n{}", g);
+            println!("\n\n{}", field);
+            field.remove(paddle);
+            field.remove(ball);
         }
     }
-    return score;
+    score
 }
 
 fn main() {
     let input = std::fs::read_to_string("./day13/input.txt").unwrap();
     let vm = input.parse::<Vm>().unwrap();
     println!("Part 1: {}", part1(vm.clone()));
-    println!("Part 2: {}", part2(vm, true));
+    // `--record PATH` saves the joystick moves of part 2, and `--replay PATH`
+    // plays a saved session back instead of running the AI
+    let mut args = std::env::args().skip(1);
+    let score = match (args.next().as_deref(), args.next()) {
+        (Some("--record"), Some(path)) => {
+            let mut log = InputLog::new();
+            let score = part2(vm, true, Joystick::Record(&mut log));
+            log.save(path).unwrap();
+            score
+        }
+        (Some("--replay"), Some(path)) => {
+            let log = InputLog::load(path).unwrap();
+            part2(vm, true, Joystick::Replay(log.play()))
+        }
+        _ => part2(vm, true, Joystick::Ai),
+    };
+    println!("Part 2: {}", score);
 }
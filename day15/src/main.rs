@@ -1,6 +1,5 @@
-use grid::{Coord, Direction, Grid};
+use grid::{bfs, Coord, Direction, Rotation, SparseGrid};
 use intcode::Vm;
-use std::collections::HashMap;
 
 enum Status {
     Unchanged,
@@ -9,7 +8,7 @@ enum Status {
 }
 
 struct Game {
-    map: HashMap<Coord, char>,
+    map: SparseGrid<char>,
     last: Coord,
     vm: Vm,
 }
@@ -17,7 +16,7 @@ struct Game {
 impl Game {
     pub fn new(vm: Vm) -> Game {
         Game {
-            map: HashMap::default(),
+            map: SparseGrid::new(' '),
             last: Coord::new(0, 0),
             vm,
         }
@@ -53,49 +52,46 @@ impl Game {
         Ok(stat)
     }
 
-    pub fn grid(&self) -> Grid<char> {
-        Coord::to_grid(self.map.clone())
-    }
-
-    pub fn sequence(&mut self, steps: &[Direction]) -> Result<usize, intcode::Error> {
-        steps.iter().try_fold(0, |acc, &d| match self.step(d) {
-            Ok(Status::Success) => Ok(acc + 1),
-            Err(e) => Err(e),
-            _ => Ok(acc),
-        })
-    }
-
-    pub fn find_edge(&mut self, edge: Direction, opp: Direction) -> usize {
-        let mut total = 0;
-        loop {
-            let mut steps = 0;
-            while let Ok(Status::Success) = self.step(edge) {
-                steps += 1;
+    /// Map every position the droid can reach, depth first, bringing it
+    /// back to where it started once each branch has been explored
+    pub fn explore(&mut self) -> Result<(), intcode::Error> {
+        if !self.map.contains(self.last) {
+            self.map.insert(self.last, '.');
+        }
+        for &dir in &Direction::ALL {
+            if self.map.contains(self.last.move_one(dir)) {
+                continue;
             }
-            if steps > 1 {
-                match self.step(opp) {
-                    Ok(Status::Success) => steps += 1,
-                    _ => return total + steps,
-                }
+            if let Status::Unchanged = self.step(dir)? {
+                continue;
             }
-            total += steps;
+            self.explore()?;
+            self.step(dir.rotate(Rotation::Left).rotate(Rotation::Left))?;
         }
+        Ok(())
     }
 }
 
 fn main() {
     let input = std::fs::read_to_string("./day15/input.txt").unwrap();
-    let mut vm = input.parse::<Vm>().unwrap();
+    let vm = input.parse::<Vm>().unwrap();
 
     let mut game = Game::new(vm);
+    game.explore().unwrap();
+    println!("{}\n", game.map);
 
-    while game.find_edge(Direction::Up, Direction::Down) > 1 {
-        let _ = game.step(Direction::Left);
-    }
-
-    while game.find_edge(Direction::Left, Direction::Right) > 1 {
-        let _ = game.step(Direction::Down);
-    }
-
-    println!("\n\n{}", game.grid());
+    let oxygen = game
+        .map
+        .iter()
+        .find(|(_, &c)| c == 'o')
+        .map(|(&pos, _)| pos)
+        .unwrap();
+    let open = |&c: &char| c != '#';
+    let from_start = bfs(&game.map, Coord::new(0, 0), open);
+    let steps = from_start
+        .distance(oxygen)
+        .expect("oxygen system is unreachable");
+    println!("Part 1: {}", steps);
+    let (_, minutes) = bfs(&game.map, oxygen, open).farthest().unwrap();
+    println!("Part 2: {}", minutes);
 }
@@ -1,22 +1,14 @@
-use grid::{Coord, Direction, Grid, Point, Rotation};
+use grid::{Direction, Grid, Point, Rotation};
 use intcode::Vm;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt::Display;
 
 fn alignment(grid: &Grid<Position>, pt: Point) -> Option<usize> {
-    use Direction::*;
     if grid[pt] == Position::Scaffold {
-        let neighbors = [
-            pt.move_one(Down),
-            pt.move_one(Left),
-            pt.move_one(Right),
-            pt.move_one(Up),
-        ];
-
-        let c = neighbors
-            .iter()
-            .filter(|&&p| p != pt && p.x < grid.cols && p.y < grid.rows)
-            .filter(|&&p| grid[p] == Position::Scaffold)
+        let c = grid
+            .neighbors4(pt)
+            .filter(|&p| grid[p] == Position::Scaffold)
             .count();
 
         if c == 4 {
@@ -30,54 +22,23 @@ fn alignment(grid: &Grid<Position>, pt: Point) -> Option<usize> {
 }
 
 fn position_grid(mut vm: Vm) -> Grid<Position> {
-    let mut data = HashMap::new();
-    let mut last = Coord::new(0, 0);
+    let mut view = String::new();
     while let Ok(out) = vm.run(std::iter::empty(), false) {
-        let c = out as u8 as char;
-        if c == '\n' {
-            last.y += 1;
-            last.x = 0;
-        } else {
-            let pos = match c {
-                '#' => Position::Scaffold,
-                '.' => Position::Empty,
-                '^' => Position::Robot(Direction::Up),
-                _ => panic!("unknown {}", c),
-            };
-            data.insert(last, pos);
-            last.x += 1;
-        }
+        view.push(out as u8 as char);
     }
-
-    Coord::to_grid(data)
+    view.parse().unwrap()
 }
 
 fn find_end(grid: &Grid<Position>) -> Option<Point> {
-    use Direction::*;
-    for (pt, pos) in grid.iter() {
-        match pos {
-            Position::Scaffold => {
-                let neighbors = [
-                    pt.move_one(Down),
-                    pt.move_one(Left),
-                    pt.move_one(Right),
-                    pt.move_one(Up),
-                ];
-
-                if neighbors
-                    .iter()
-                    .filter(|&&p| p != pt && p.x < grid.cols && p.y < grid.rows)
-                    .filter(|&&p| grid[p] != Position::Empty)
-                    .count()
-                    == 1
-                {
-                    return Some(pt);
-                }
-            }
-            _ => {}
-        }
-    }
-    None
+    grid.iter()
+        .filter(|(_, pos)| **pos == Position::Scaffold)
+        .map(|(pt, _)| pt)
+        .find(|&pt| {
+            grid.neighbors4(pt)
+                .filter(|&p| grid[p] != Position::Empty)
+                .count()
+                == 1
+        })
 }
 
 #[derive(Clone)]
@@ -97,18 +58,15 @@ struct Move {
 impl<'g> Tracer<'g> {
     fn max_path(&self, dir: Direction) -> usize {
         let mut pt = self.pt;
-        let mut steps: usize = 0;
-        while self.grid[pt] != Position::Empty {
-            if (pt.x == 0 && dir == Direction::Left) || (pt.y == 0 && dir == Direction::Up) {
-                return steps;
-            }
-            pt = pt.move_one(dir);
+        let mut steps = 0;
+        while let Some(next) = pt
+            .checked_move(dir)
+            .filter(|&p| self.grid.in_bounds(p) && self.grid[p] != Position::Empty)
+        {
+            pt = next;
             steps += 1;
-            if !self.grid.in_bounds(pt) {
-                break;
-            }
         }
-        steps.saturating_sub(1)
+        steps
     }
 
     fn find_best_move(&self) -> Move {
@@ -138,22 +96,16 @@ impl<'g> Tracer<'g> {
     fn run(grid: &Grid<Position>) -> Option<Vec<Move>> {
         let start = grid
             .iter()
-            .filter(|(_, pos)| {
-                if let Position::Robot(_) = pos {
-                    true
-                } else {
-                    false
-                }
-            })
+            .filter(|(_, pos)| matches!(pos, Position::Robot(_)))
             .map(|(pt, _)| pt)
             .next()
             .unwrap();
-        let end = find_end(&grid).unwrap();
+        let end = find_end(grid).unwrap();
 
         let mut t = Tracer {
             dir: Direction::Up,
             pt: start,
-            grid: &grid,
+            grid,
             moves: Vec::new(),
         };
 
@@ -212,10 +164,7 @@ fn compile(moves: Vec<Move>) -> Option<Program> {
         }
 
         for (k, occur) in map {
-            savings
-                .entry(occur * i * k.len())
-                .or_insert_with(Vec::new)
-                .push(k);
+            savings.entry(occur * i * k.len()).or_default().push(k);
         }
     }
 
@@ -225,7 +174,7 @@ fn compile(moves: Vec<Move>) -> Option<Program> {
     let mut routines = HashMap::new();
 
     for i in 0..3 {
-        let ch = (i as u8 + 'A' as u8) as char;
+        let ch = (i as u8 + b'A') as char;
 
         // We may have already removed the full substring, so loop until we
         // find a full match
@@ -266,8 +215,8 @@ fn part2(mut vm: Vm) -> Option<isize> {
         .chain(a)
         .chain(b)
         .chain(c)
-        .chain(std::iter::once('n' as u8))
-        .chain(std::iter::once('\n' as u8));
+        .chain(std::iter::once(b'n'))
+        .chain(std::iter::once(b'\n'));
 
     let mut last = 0;
     loop {
@@ -283,13 +232,26 @@ fn part2(mut vm: Vm) -> Option<isize> {
     Some(last)
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
 enum Position {
+    #[default]
     Empty,
     Scaffold,
     Robot(Direction),
 }
 
+impl TryFrom<char> for Position {
+    type Error = String;
+    fn try_from(c: char) -> Result<Position, String> {
+        match c {
+            '#' => Ok(Position::Scaffold),
+            '.' => Ok(Position::Empty),
+            '^' => Ok(Position::Robot(Direction::Up)),
+            _ => Err(format!("unknown {}", c)),
+        }
+    }
+}
+
 impl Display for Position {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
@@ -309,12 +271,6 @@ impl Display for Position {
     }
 }
 
-impl Default for Position {
-    fn default() -> Position {
-        Position::Empty
-    }
-}
-
 impl Display for Move {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
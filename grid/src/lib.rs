@@ -3,6 +3,15 @@ use std::convert::TryFrom;
 use std::fmt::Display;
 use std::ops::{Index, IndexMut};
 
+mod path;
+mod sparse;
+mod text;
+mod transform;
+
+pub use path::{astar, bfs, bfs_multi, dijkstra, Map, Paths};
+pub use sparse::SparseGrid;
+pub use text::ParseError;
+
 /// A point on an XY plane where the top-left position has coordinate of (0,0)
 #[derive(Copy, Clone, Default, PartialEq, PartialOrd, Ord, Eq, Hash)]
 pub struct Point {
@@ -25,6 +34,18 @@ pub enum Direction {
     Right,
 }
 
+/// Offsets of the eight surrounding positions, in reading order
+const AROUND: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Ord, Eq, Hash)]
 pub enum Rotation {
     Left,
@@ -94,13 +115,37 @@ impl<T> Grid<T> {
         }
     }
 
-    pub fn iter(&self) -> std::iter::Zip<PointIter, std::slice::Iter<T>> {
+    pub fn iter(&self) -> std::iter::Zip<PointIter, std::slice::Iter<'_, T>> {
         self.iter_points().zip(self.grid.iter())
     }
 
     pub fn in_bounds(&self, pt: Point) -> bool {
         pt.x < self.cols && pt.y < self.rows
     }
+
+    /// Points up, down, left and right of `pt` that are inside the grid,
+    /// along with the direction of each
+    pub fn neighbors4_dir(&self, pt: Point) -> impl Iterator<Item = (Direction, Point)> + '_ {
+        Direction::ALL.iter().filter_map(move |&dir| {
+            pt.checked_move(dir)
+                .filter(|&p| self.in_bounds(p))
+                .map(|p| (dir, p))
+        })
+    }
+
+    /// Points up, down, left and right of `pt` that are inside the grid
+    pub fn neighbors4(&self, pt: Point) -> impl Iterator<Item = Point> + '_ {
+        self.neighbors4_dir(pt).map(|(_, p)| p)
+    }
+
+    /// Points surrounding `pt`, diagonals included, that are inside the grid
+    pub fn neighbors8(&self, pt: Point) -> impl Iterator<Item = Point> + '_ {
+        AROUND.iter().filter_map(move |&(dx, dy)| {
+            let x = usize::try_from(pt.x as isize + dx).ok()?;
+            let y = usize::try_from(pt.y as isize + dy).ok()?;
+            Some(Point { x, y }).filter(|&p| self.in_bounds(p))
+        })
+    }
 }
 
 impl Point {
@@ -133,6 +178,16 @@ impl Point {
             },
         }
     }
+
+    /// Like [`Point::move_one`], but `None` rather than staying put when
+    /// moving up or left from the edge of the plane
+    pub fn checked_move(self, dir: Direction) -> Option<Point> {
+        match dir {
+            Direction::Up if self.y == 0 => None,
+            Direction::Left if self.x == 0 => None,
+            _ => Some(self.move_one(dir)),
+        }
+    }
 }
 
 impl<T: Display> Display for Grid<T> {
@@ -163,27 +218,11 @@ impl Coord {
     /// to (0,0), and all other points are modified to have the same relative
     /// position
     pub fn to_grid<T: Default + Clone>(data: HashMap<Coord, T>) -> Grid<T> {
-        assert!(data.len() > 0);
-        let min_x = data.keys().map(|c| c.x).min().unwrap();
-        let min_y = data.keys().map(|c| c.y).min().unwrap();
-        let max_x = data.keys().map(|c| c.x).max().expect("max_x fail");
-        let max_y = data.keys().map(|c| c.y).max().expect("max_y fail");
-
-        let cols = usize::try_from(max_x - min_x).expect("cols failed") + 1;
-        let rows = usize::try_from(max_y - min_y).expect("rows failed") + 1;
-
-        let g = std::iter::repeat(T::default())
-            .take(cols * rows)
-            .collect::<Vec<T>>();
-
-        let mut grid = Grid::new(cols, rows, g);
-
+        let bounds = BoundingBox::from_points(data.keys().copied()).expect("no coordinates");
+        let (cols, rows) = (bounds.width(), bounds.height());
+        let mut grid = Grid::new(cols, rows, vec![T::default(); cols * rows]);
         for (coord, val) in data {
-            let pt = Point::new(
-                (coord.x + min_x.abs()) as usize,
-                (coord.y + min_y.abs()) as usize,
-            );
-            grid[pt] = val;
+            grid[bounds.to_point(coord).unwrap()] = val;
         }
         grid
     }
@@ -208,6 +247,83 @@ impl Coord {
             },
         }
     }
+
+    /// Coordinates up, down, left and right of this one, along with the
+    /// direction of each
+    pub fn neighbors4_dir(self) -> impl Iterator<Item = (Direction, Coord)> {
+        Direction::ALL
+            .iter()
+            .map(move |&dir| (dir, self.move_one(dir)))
+    }
+
+    /// Coordinates up, down, left and right of this one
+    pub fn neighbors4(self) -> impl Iterator<Item = Coord> {
+        self.neighbors4_dir().map(|(_, c)| c)
+    }
+
+    /// Coordinates surrounding this one, diagonals included
+    pub fn neighbors8(self) -> impl Iterator<Item = Coord> {
+        AROUND
+            .iter()
+            .map(move |&(dx, dy)| Coord::new(self.x + dx, self.y + dy))
+    }
+}
+
+/// The smallest rectangle holding a set of [`Coord`]s, with both corners
+/// included
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BoundingBox {
+    pub min: Coord,
+    pub max: Coord,
+}
+
+impl BoundingBox {
+    /// The bounds of `points`, or `None` if there aren't any
+    pub fn from_points<I: IntoIterator<Item = Coord>>(points: I) -> Option<BoundingBox> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        let mut bounds = BoundingBox {
+            min: first,
+            max: first,
+        };
+        points.for_each(|c| bounds.extend(c));
+        Some(bounds)
+    }
+
+    /// Grow the box to hold `c`
+    pub fn extend(&mut self, c: Coord) {
+        self.min = Coord::new(self.min.x.min(c.x), self.min.y.min(c.y));
+        self.max = Coord::new(self.max.x.max(c.x), self.max.y.max(c.y));
+    }
+
+    pub fn width(&self) -> usize {
+        (self.max.x - self.min.x) as usize + 1
+    }
+
+    pub fn height(&self) -> usize {
+        (self.max.y - self.min.y) as usize + 1
+    }
+
+    pub fn contains(&self, c: Coord) -> bool {
+        (self.min.x..=self.max.x).contains(&c.x) && (self.min.y..=self.max.y).contains(&c.y)
+    }
+
+    /// Position of `c` in a grid of the box's size, whose top-left point is
+    /// the box's top-left corner
+    pub fn to_point(&self, c: Coord) -> Option<Point> {
+        if !self.contains(c) {
+            return None;
+        }
+        Some(Point::new(
+            (c.x - self.min.x) as usize,
+            (c.y - self.min.y) as usize,
+        ))
+    }
+
+    /// The inverse of [`BoundingBox::to_point`]
+    pub fn to_coord(&self, pt: Point) -> Coord {
+        Coord::new(self.min.x + pt.x as isize, self.min.y + pt.y as isize)
+    }
 }
 
 impl<T: Clone> Clone for Grid<T> {
@@ -221,6 +337,13 @@ impl<T: Clone> Clone for Grid<T> {
 }
 
 impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+    ];
+
     pub fn rotate(self, rotation: Rotation) -> Direction {
         match rotation {
             Rotation::Left => match self {
@@ -271,3 +394,57 @@ fn grid_iter() {
         ]
     );
 }
+
+#[test]
+fn neighbors() {
+    let grid = Grid::new(3, 2, vec![0; 6]);
+    let corner = grid.neighbors4_dir(Point::new(0, 0)).collect::<Vec<_>>();
+    assert_eq!(
+        corner,
+        vec![
+            (Direction::Down, Point::new(0, 1)),
+            (Direction::Right, Point::new(1, 0))
+        ]
+    );
+    assert_eq!(
+        grid.neighbors4(Point::new(2, 1)).collect::<Vec<_>>(),
+        vec![Point::new(2, 0), Point::new(1, 1)]
+    );
+    assert_eq!(
+        grid.neighbors8(Point::new(1, 1)).collect::<Vec<_>>(),
+        vec![
+            Point::new(0, 0),
+            Point::new(1, 0),
+            Point::new(2, 0),
+            Point::new(0, 1),
+            Point::new(2, 1)
+        ]
+    );
+
+    // The plane of coordinates has no edges
+    let origin = Coord::new(0, 0);
+    assert_eq!(origin.neighbors4().count(), 4);
+    assert!(origin.neighbors8().any(|c| c == Coord::new(-1, -1)));
+    assert_eq!(Point::new(0, 3).checked_move(Direction::Left), None);
+}
+
+#[test]
+fn bounding_box() {
+    let points = vec![Coord::new(3, 5), Coord::new(7, 4), Coord::new(4, 6)];
+    let bounds = BoundingBox::from_points(points.iter().copied()).unwrap();
+    assert_eq!(
+        (bounds.min, bounds.max),
+        (Coord::new(3, 4), Coord::new(7, 6))
+    );
+    assert_eq!((bounds.width(), bounds.height()), (5, 3));
+    assert!(bounds.contains(Coord::new(7, 6)));
+    assert!(!bounds.contains(Coord::new(2, 5)));
+    assert_eq!(bounds.to_point(Coord::new(3, 5)), Some(Point::new(0, 1)));
+    assert_eq!(bounds.to_point(Coord::new(8, 5)), None);
+    assert_eq!(bounds.to_coord(Point::new(4, 0)), Coord::new(7, 4));
+    assert_eq!(BoundingBox::from_points(Vec::new()), None);
+
+    // Every coordinate positive, which used to index out of bounds
+    let grid = Coord::to_grid(points.into_iter().map(|c| (c, 1)).collect());
+    assert_eq!(grid.to_string(), "00001\n10000\n01000");
+}
@@ -0,0 +1,278 @@
+//! Shortest paths over grids and sparse maps.
+//!
+//! The searches work on anything implementing [`Map`], which is a dense
+//! [`Grid`] or a `HashMap<Coord, T>` that only knows about the cells it
+//! holds. Moves are up, down, left and right. Whether a cell can be entered,
+//! and what it costs, is decided by a closure on its contents.
+use crate::{Coord, Grid, Point};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::hash::{BuildHasher, Hash};
+
+/// Something that can be searched for paths
+pub trait Map {
+    type Pos: Copy + Eq + Hash + Ord;
+    type Cell;
+
+    /// Contents of the cell at `pos`, if it is on the map
+    fn cell(&self, pos: Self::Pos) -> Option<&Self::Cell>;
+
+    /// Positions one step away from `pos` that are on the map
+    fn adjacent(&self, pos: Self::Pos) -> Vec<Self::Pos>;
+
+    /// Number of steps between two positions if nothing were in the way
+    fn estimate(&self, from: Self::Pos, to: Self::Pos) -> usize;
+}
+
+impl<T> Map for Grid<T> {
+    type Pos = Point;
+    type Cell = T;
+
+    fn cell(&self, pos: Point) -> Option<&T> {
+        if self.in_bounds(pos) {
+            Some(&self[pos])
+        } else {
+            None
+        }
+    }
+
+    fn adjacent(&self, pos: Point) -> Vec<Point> {
+        self.neighbors4(pos).collect()
+    }
+
+    fn estimate(&self, from: Point, to: Point) -> usize {
+        from.manhattan(to)
+    }
+}
+
+impl<T, S: BuildHasher> Map for HashMap<Coord, T, S> {
+    type Pos = Coord;
+    type Cell = T;
+
+    fn cell(&self, pos: Coord) -> Option<&T> {
+        self.get(&pos)
+    }
+
+    fn adjacent(&self, pos: Coord) -> Vec<Coord> {
+        pos.neighbors4().filter(|c| self.contains_key(c)).collect()
+    }
+
+    fn estimate(&self, from: Coord, to: Coord) -> usize {
+        ((from.x - to.x).abs() + (from.y - to.y).abs()) as usize
+    }
+}
+
+/// Distances from the start of a search to every position it reached, and
+/// the way back
+#[derive(Clone, Debug, PartialEq)]
+pub struct Paths<P: Eq + Hash> {
+    pub distances: HashMap<P, usize>,
+    previous: HashMap<P, P>,
+}
+
+impl<P: Copy + Eq + Hash + Ord> Paths<P> {
+    fn new() -> Paths<P> {
+        Paths {
+            distances: HashMap::new(),
+            previous: HashMap::new(),
+        }
+    }
+
+    pub fn distance(&self, to: P) -> Option<usize> {
+        self.distances.get(&to).copied()
+    }
+
+    /// Positions along a shortest path to `to`, from where it started up to
+    /// and including `to`
+    pub fn path(&self, to: P) -> Option<Vec<P>> {
+        self.distances.get(&to)?;
+        let mut path = vec![to];
+        while let Some(&prev) = self.previous.get(path.last().unwrap()) {
+            path.push(prev);
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// The position furthest from the start, and its distance. Ties go to
+    /// the smallest position
+    pub fn farthest(&self) -> Option<(P, usize)> {
+        self.distances
+            .iter()
+            .max_by_key(|&(&pos, &dist)| (dist, Reverse(pos)))
+            .map(|(&pos, &dist)| (pos, dist))
+    }
+}
+
+/// Breadth first search from `start`, entering only the cells for which
+/// `passable` is true
+pub fn bfs<M, F>(map: &M, start: M::Pos, passable: F) -> Paths<M::Pos>
+where
+    M: Map,
+    F: Fn(&M::Cell) -> bool,
+{
+    bfs_multi(map, std::iter::once(start), passable)
+}
+
+/// Breadth first search from several positions at once, so that the
+/// distance to each position is from the closest start. This times a flood
+/// fill that spreads from every start at the same rate
+pub fn bfs_multi<M, I, F>(map: &M, starts: I, passable: F) -> Paths<M::Pos>
+where
+    M: Map,
+    I: IntoIterator<Item = M::Pos>,
+    F: Fn(&M::Cell) -> bool,
+{
+    let mut paths = Paths::new();
+    let mut queue = VecDeque::new();
+    for start in starts {
+        if paths.distances.insert(start, 0).is_none() {
+            queue.push_back(start);
+        }
+    }
+    while let Some(pos) = queue.pop_front() {
+        let dist = paths.distances[&pos];
+        for next in map.adjacent(pos) {
+            if paths.distances.contains_key(&next) || !map.cell(next).is_some_and(&passable) {
+                continue;
+            }
+            paths.distances.insert(next, dist + 1);
+            paths.previous.insert(next, pos);
+            queue.push_back(next);
+        }
+    }
+    paths
+}
+
+/// Shortest paths from `start`, where `cost` gives the cost of entering a
+/// cell, or `None` if it can't be entered
+pub fn dijkstra<M, F>(map: &M, start: M::Pos, cost: F) -> Paths<M::Pos>
+where
+    M: Map,
+    F: Fn(&M::Cell) -> Option<usize>,
+{
+    let mut paths = Paths::new();
+    let mut heap = BinaryHeap::new();
+    paths.distances.insert(start, 0);
+    heap.push(Reverse((0, start)));
+    while let Some(Reverse((dist, pos))) = heap.pop() {
+        if dist > paths.distances[&pos] {
+            continue;
+        }
+        for next in map.adjacent(pos) {
+            let step = match map.cell(next).and_then(&cost) {
+                Some(step) => step,
+                None => continue,
+            };
+            let next_dist = dist + step;
+            if paths.distances.get(&next).is_none_or(|&d| next_dist < d) {
+                paths.distances.insert(next, next_dist);
+                paths.previous.insert(next, pos);
+                heap.push(Reverse((next_dist, next)));
+            }
+        }
+    }
+    paths
+}
+
+/// Shortest paths from `start` like [`dijkstra`], but guided towards `goal`
+/// and stopping once it is reached. Only the distance and path to `goal` are
+/// final; other positions may have been reached along longer routes. `cost`
+/// must be at least 1 for every cell that can be entered
+pub fn astar<M, F>(map: &M, start: M::Pos, goal: M::Pos, cost: F) -> Paths<M::Pos>
+where
+    M: Map,
+    F: Fn(&M::Cell) -> Option<usize>,
+{
+    let mut paths = Paths::new();
+    let mut heap = BinaryHeap::new();
+    paths.distances.insert(start, 0);
+    heap.push(Reverse((map.estimate(start, goal), 0, start)));
+    while let Some(Reverse((_, dist, pos))) = heap.pop() {
+        if pos == goal {
+            break;
+        }
+        if dist > paths.distances[&pos] {
+            continue;
+        }
+        for next in map.adjacent(pos) {
+            let step = match map.cell(next).and_then(&cost) {
+                Some(step) => step,
+                None => continue,
+            };
+            let next_dist = dist + step;
+            if paths.distances.get(&next).is_none_or(|&d| next_dist < d) {
+                paths.distances.insert(next, next_dist);
+                paths.previous.insert(next, pos);
+                let guess = next_dist + map.estimate(next, goal);
+                heap.push(Reverse((guess, next_dist, next)));
+            }
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+fn maze() -> Grid<char> {
+    let rows = [
+        "#########",
+        "#S..#...#",
+        "#.#.#.#.#",
+        "#.#...#E#",
+        "#########",
+    ];
+    let cells = rows.iter().flat_map(|row| row.chars()).collect();
+    Grid::new(9, rows.len(), cells)
+}
+
+#[test]
+fn shortest_paths() {
+    let grid = maze();
+    let (start, end) = (Point::new(1, 1), Point::new(7, 3));
+    let paths = bfs(&grid, start, |&c| c != '#');
+    assert_eq!(paths.distance(end), Some(12));
+    let path = paths.path(end).unwrap();
+    assert_eq!(path.len(), 13);
+    assert_eq!((path[0], path[12]), (start, end));
+    assert!(path.windows(2).all(|w| w[0].manhattan(w[1]) == 1));
+    assert_eq!(paths.distance(Point::new(0, 0)), None);
+
+    let cost = |&c: &char| if c == '#' { None } else { Some(1) };
+    assert_eq!(dijkstra(&grid, start, cost).distance(end), Some(12));
+    let guided = astar(&grid, start, end, cost);
+    assert_eq!(guided.distance(end), Some(12));
+    assert_eq!(guided.path(end), Some(path));
+    let nowhere = Point::new(0, 0);
+    assert_eq!(astar(&grid, start, nowhere, cost).distance(nowhere), None);
+}
+
+#[test]
+fn weighted() {
+    // Crossing the middle is shorter, but costs more than going around
+    let grid = Grid::new(3, 3, vec![1, 1, 1, 1, 9, 1, 1, 1, 1]);
+    let (start, end) = (Point::new(0, 1), Point::new(2, 1));
+    let cost = |&c: &usize| Some(c);
+    assert_eq!(dijkstra(&grid, start, cost).distance(end), Some(4));
+    let guided = astar(&grid, start, end, cost);
+    assert_eq!(guided.distance(end), Some(4));
+    let path = guided.path(end).unwrap();
+    assert_eq!(path.len(), 5);
+    assert!(!path.contains(&Point::new(1, 1)));
+    assert_eq!(bfs(&grid, start, |_| true).distance(end), Some(2));
+}
+
+#[test]
+fn sparse_flood() {
+    // Two sources spreading through an L shaped corridor
+    let mut map = HashMap::new();
+    for x in 0..6 {
+        map.insert(Coord::new(x, 0), ());
+    }
+    for y in 1..4 {
+        map.insert(Coord::new(5, y), ());
+    }
+    let flood = bfs_multi(&map, vec![Coord::new(0, 0), Coord::new(5, 3)], |_| true);
+    assert_eq!(flood.distance(Coord::new(3, 0)), Some(3));
+    assert_eq!(flood.distance(Coord::new(5, 1)), Some(2));
+    assert_eq!(flood.farthest(), Some((Coord::new(4, 0), 4)));
+}
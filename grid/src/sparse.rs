@@ -0,0 +1,218 @@
+//! A grid over the whole plane that only stores the cells that were set.
+use crate::{BoundingBox, Coord, Grid, Map};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::ops::{Index, IndexMut};
+
+/// Cells on an infinite plane, keyed by [`Coord`]. Cells that were never
+/// set read as a default value, and print as a background character
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparseGrid<T> {
+    cells: HashMap<Coord, T>,
+    default: T,
+    background: char,
+    /// Bounds of every coordinate ever set
+    bounds: Option<BoundingBox>,
+}
+
+impl<T> SparseGrid<T> {
+    /// An empty grid where every cell reads as `default`, printed as spaces
+    pub fn new(default: T) -> SparseGrid<T> {
+        SparseGrid {
+            cells: HashMap::new(),
+            default,
+            background: ' ',
+            bounds: None,
+        }
+    }
+
+    /// Character printed for cells that haven't been set
+    pub fn background(mut self, background: char) -> SparseGrid<T> {
+        self.background = background;
+        self
+    }
+
+    pub fn get(&self, c: Coord) -> Option<&T> {
+        self.cells.get(&c)
+    }
+
+    pub fn contains(&self, c: Coord) -> bool {
+        self.cells.contains_key(&c)
+    }
+
+    pub fn insert(&mut self, c: Coord, value: T) -> Option<T> {
+        self.grow(c);
+        self.cells.insert(c, value)
+    }
+
+    /// Forget the cell at `c`. The bounds stay as they were
+    pub fn remove(&mut self, c: Coord) -> Option<T> {
+        self.cells.remove(&c)
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// Cells that have been set, in no particular order
+    pub fn iter(&self) -> std::collections::hash_map::Iter<'_, Coord, T> {
+        self.cells.iter()
+    }
+
+    /// The smallest rectangle holding every cell that has been set
+    pub fn bounds(&self) -> Option<BoundingBox> {
+        self.bounds
+    }
+
+    fn grow(&mut self, c: Coord) {
+        match &mut self.bounds {
+            Some(bounds) => bounds.extend(c),
+            None => self.bounds = BoundingBox::from_points(Some(c)),
+        }
+    }
+
+    /// Size of the bounds in columns and rows
+    fn size(&self) -> (usize, usize) {
+        self.bounds.map_or((0, 0), |b| (b.width(), b.height()))
+    }
+}
+
+impl<T: Clone> SparseGrid<T> {
+    /// A dense copy of the cells within the bounds, moved so that the
+    /// top-left corner is at (0,0)
+    pub fn to_grid(&self) -> Grid<T> {
+        let (cols, rows) = self.size();
+        let mut grid = Grid::new(cols, rows, vec![self.default.clone(); cols * rows]);
+        if let Some(bounds) = self.bounds {
+            for (&c, value) in &self.cells {
+                grid[bounds.to_point(c).unwrap()] = value.clone();
+            }
+        }
+        grid
+    }
+
+    /// Like [`SparseGrid::to_grid`], but moving the cells rather than
+    /// cloning them
+    pub fn into_grid(self) -> Grid<T> {
+        let (cols, rows) = self.size();
+        let mut grid = Grid::new(cols, rows, vec![self.default; cols * rows]);
+        if let Some(bounds) = self.bounds {
+            for (c, value) in self.cells {
+                grid[bounds.to_point(c).unwrap()] = value;
+            }
+        }
+        grid
+    }
+}
+
+impl<T: Default> Default for SparseGrid<T> {
+    fn default() -> SparseGrid<T> {
+        SparseGrid::new(T::default())
+    }
+}
+
+/// Every cell of the grid, at the same coordinates as its points
+impl<T: Default> From<Grid<T>> for SparseGrid<T> {
+    fn from(grid: Grid<T>) -> SparseGrid<T> {
+        let mut sparse = SparseGrid::default();
+        let points = grid.iter_points();
+        for (pt, value) in points.zip(grid.grid) {
+            sparse.insert(Coord::new(pt.x as isize, pt.y as isize), value);
+        }
+        sparse
+    }
+}
+
+impl<T: Clone> From<SparseGrid<T>> for Grid<T> {
+    fn from(sparse: SparseGrid<T>) -> Grid<T> {
+        sparse.into_grid()
+    }
+}
+
+/// Cells that haven't been set read as the default
+impl<T> Index<Coord> for SparseGrid<T> {
+    type Output = T;
+    fn index(&self, c: Coord) -> &T {
+        self.cells.get(&c).unwrap_or(&self.default)
+    }
+}
+
+/// Cells that haven't been set are set to the default first
+impl<T: Clone> IndexMut<Coord> for SparseGrid<T> {
+    fn index_mut(&mut self, c: Coord) -> &mut T {
+        self.grow(c);
+        let default = &self.default;
+        self.cells.entry(c).or_insert_with(|| default.clone())
+    }
+}
+
+/// Paths only go through cells that have been set
+impl<T> Map for SparseGrid<T> {
+    type Pos = Coord;
+    type Cell = T;
+
+    fn cell(&self, pos: Coord) -> Option<&T> {
+        self.cells.cell(pos)
+    }
+
+    fn adjacent(&self, pos: Coord) -> Vec<Coord> {
+        self.cells.adjacent(pos)
+    }
+
+    fn estimate(&self, from: Coord, to: Coord) -> usize {
+        self.cells.estimate(from, to)
+    }
+}
+
+impl<T: Display> Display for SparseGrid<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (min, max) = match self.bounds {
+            Some(bounds) => (bounds.min, bounds.max),
+            None => return Ok(()),
+        };
+        for y in min.y..=max.y {
+            if y > min.y {
+                writeln!(f)?;
+            }
+            for x in min.x..=max.x {
+                match self.cells.get(&Coord::new(x, y)) {
+                    Some(value) => write!(f, "{}", value)?,
+                    None => write!(f, "{}", self.background)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn sparse_grid() {
+    let mut sparse = SparseGrid::new('.').background(' ');
+    assert_eq!(sparse.bounds(), None);
+    assert_eq!(sparse.to_string(), "");
+    sparse.insert(Coord::new(-1, 2), '#');
+    sparse.insert(Coord::new(1, 0), '#');
+    sparse[Coord::new(0, 1)] = 'o';
+    let bounds = sparse.bounds().unwrap();
+    assert_eq!(
+        (bounds.min, bounds.max),
+        (Coord::new(-1, 0), Coord::new(1, 2))
+    );
+    assert_eq!(sparse[Coord::new(5, 5)], '.');
+    assert_eq!(sparse.len(), 3);
+    assert_eq!(sparse.to_string(), "  #\n o \n#  ");
+
+    let grid = sparse.to_grid();
+    assert_eq!(grid.to_string(), "..#\n.o.\n#..");
+    assert_eq!(sparse.clone().into_grid().to_string(), grid.to_string());
+
+    // Back again, with the top-left corner now at the origin
+    let dense = SparseGrid::from(grid);
+    assert_eq!(dense.len(), 9);
+    assert_eq!(dense[Coord::new(1, 1)], 'o');
+    assert_eq!(dense.bounds().unwrap().min, Coord::new(0, 0));
+}
@@ -0,0 +1,158 @@
+//! Reading grids from text, and writing them back.
+use crate::{Grid, Point};
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Why text couldn't be read as a grid. Lines and columns count from 1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError<E> {
+    /// A line is a different length from the first one
+    Ragged {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// The character mapping rejected a character
+    Cell {
+        line: usize,
+        column: usize,
+        error: E,
+    },
+}
+
+impl<E: Display> Display for ParseError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::Ragged {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} characters, found {}",
+                line, expected, found
+            ),
+            ParseError::Cell {
+                line,
+                column,
+                error,
+            } => write!(f, "line {}, column {}: {}", line, column, error),
+        }
+    }
+}
+
+impl<E: std::fmt::Debug + Display> std::error::Error for ParseError<E> {}
+
+/// A grid and the markers found in it
+type Marked<T> = (Grid<T>, Vec<(Point, char)>);
+
+impl<T> Grid<T> {
+    /// Read lines of characters into a grid, one cell per character.
+    /// Trailing blank lines are ignored, but every other line must be the
+    /// same length
+    pub fn parse<E, F>(text: &str, cell: F) -> Result<Grid<T>, ParseError<E>>
+    where
+        F: FnMut(char) -> Result<T, E>,
+    {
+        Grid::parse_marked(text, &[], cell).map(|(grid, _)| grid)
+    }
+
+    /// Like [`Grid::parse`], also returning where any of the `markers`
+    /// characters were found, in reading order. Markers are still passed to
+    /// `cell` like any other character
+    pub fn parse_marked<E, F>(
+        text: &str,
+        markers: &[char],
+        mut cell: F,
+    ) -> Result<Marked<T>, ParseError<E>>
+    where
+        F: FnMut(char) -> Result<T, E>,
+    {
+        let mut cells = Vec::new();
+        let mut found = Vec::new();
+        let mut cols = None;
+        let lines = text.trim_end_matches(&['\n', '\r'][..]).lines();
+        let mut rows = 0;
+        for (y, line) in lines.enumerate() {
+            let len = line.chars().count();
+            let expected = *cols.get_or_insert(len);
+            if len != expected {
+                return Err(ParseError::Ragged {
+                    line: y + 1,
+                    expected,
+                    found: len,
+                });
+            }
+            for (x, ch) in line.chars().enumerate() {
+                if markers.contains(&ch) {
+                    found.push((Point::new(x, y), ch));
+                }
+                cells.push(cell(ch).map_err(|error| ParseError::Cell {
+                    line: y + 1,
+                    column: x + 1,
+                    error,
+                })?);
+            }
+            rows += 1;
+        }
+        Ok((Grid::new(cols.unwrap_or(0), rows, cells), found))
+    }
+
+    /// Write the grid out as lines of characters, the inverse of
+    /// [`Grid::parse`]
+    pub fn render<F: Fn(&T) -> char>(&self, cell: F) -> String {
+        let mut out = String::with_capacity((self.cols + 1) * self.rows);
+        for (n, row) in self.grid.chunks(self.cols.max(1)).enumerate() {
+            if n > 0 {
+                out.push('\n');
+            }
+            out.extend(row.iter().map(&cell));
+        }
+        out
+    }
+}
+
+/// Parses a grid with each character converted by `T`'s [`TryFrom`]
+impl<T: TryFrom<char>> FromStr for Grid<T> {
+    type Err = ParseError<T::Error>;
+    fn from_str(s: &str) -> Result<Grid<T>, Self::Err> {
+        Grid::parse(s, T::try_from)
+    }
+}
+
+#[test]
+fn parse_and_render() {
+    let text = "#.#\n.^.\n";
+    let (grid, markers) = Grid::parse_marked(text, &['^'], |c| Ok::<_, ()>(c == '#')).unwrap();
+    assert_eq!((grid.cols, grid.rows), (3, 2));
+    assert!(grid[Point::new(2, 0)]);
+    assert_eq!(markers, vec![(Point::new(1, 1), '^')]);
+    assert_eq!(
+        grid.render(|&wall| if wall { '#' } else { '.' }),
+        "#.#\n..."
+    );
+
+    let digits = "123\n45\n".parse::<Grid<char>>();
+    assert_eq!(
+        digits.err(),
+        Some(ParseError::Ragged {
+            line: 2,
+            expected: 3,
+            found: 2
+        })
+    );
+    let error = Grid::parse("..\n.x", |c| match c {
+        '.' => Ok(()),
+        c => Err(format!("unexpected '{}'", c)),
+    })
+    .err()
+    .unwrap();
+    assert_eq!(error.to_string(), "line 2, column 2: unexpected 'x'");
+
+    let empty = "".parse::<Grid<char>>().unwrap();
+    assert_eq!(
+        (empty.cols, empty.rows, empty.render(|&c| c)),
+        (0, 0, String::new())
+    );
+}
@@ -0,0 +1,117 @@
+//! Rotating, flipping and resizing grids. Each transformation returns a new
+//! grid and leaves the original alone.
+use crate::{BoundingBox, Grid, Point};
+
+impl<T: Clone> Grid<T> {
+    /// A `cols` by `rows` grid where each point takes its value from the
+    /// point `from` maps it to in this grid
+    fn remap<F: Fn(Point) -> Point>(&self, cols: usize, rows: usize, from: F) -> Grid<T> {
+        let grid = (0..rows)
+            .flat_map(|y| (0..cols).map(move |x| Point::new(x, y)))
+            .map(|pt| self[from(pt)].clone())
+            .collect();
+        Grid::new(cols, rows, grid)
+    }
+
+    /// Swap rows and columns, mirroring across the top-left to bottom-right
+    /// diagonal
+    pub fn transpose(&self) -> Grid<T> {
+        self.remap(self.rows, self.cols, |pt| Point::new(pt.y, pt.x))
+    }
+
+    /// Turn a quarter clockwise, so the top row becomes the right column
+    pub fn rotate_cw(&self) -> Grid<T> {
+        let rows = self.rows;
+        self.remap(rows, self.cols, |pt| Point::new(pt.y, rows - 1 - pt.x))
+    }
+
+    /// Turn a quarter anticlockwise, so the top row becomes the left column
+    pub fn rotate_ccw(&self) -> Grid<T> {
+        let cols = self.cols;
+        self.remap(self.rows, cols, |pt| Point::new(cols - 1 - pt.y, pt.x))
+    }
+
+    /// Mirror left to right
+    pub fn flip_h(&self) -> Grid<T> {
+        let cols = self.cols;
+        self.remap(cols, self.rows, |pt| Point::new(cols - 1 - pt.x, pt.y))
+    }
+
+    /// Mirror top to bottom
+    pub fn flip_v(&self) -> Grid<T> {
+        let rows = self.rows;
+        self.remap(self.cols, rows, |pt| Point::new(pt.x, rows - 1 - pt.y))
+    }
+
+    /// The cells inside `bounds`, reading the grid's points as coordinates.
+    /// Panics if the box reaches outside the grid, as indexing would
+    pub fn crop(&self, bounds: BoundingBox) -> Grid<T> {
+        let (min, max) = (bounds.min, bounds.max);
+        assert!(
+            min.x >= 0 && min.y >= 0 && self.in_bounds(Point::new(max.x as usize, max.y as usize)),
+            "crop {:?}..{:?} outside a {}x{} grid",
+            min,
+            max,
+            self.cols,
+            self.rows
+        );
+        self.remap(bounds.width(), bounds.height(), |pt| {
+            let c = bounds.to_coord(pt);
+            Point::new(c.x as usize, c.y as usize)
+        })
+    }
+
+    /// Surround the grid with a border `n` cells wide of `fill`
+    pub fn pad(&self, n: usize, fill: T) -> Grid<T> {
+        let (cols, rows) = (self.cols + 2 * n, self.rows + 2 * n);
+        let mut padded = Grid::new(cols, rows, vec![fill; cols * rows]);
+        for (pt, value) in self.iter() {
+            padded[Point::new(pt.x + n, pt.y + n)] = value.clone();
+        }
+        padded
+    }
+
+    /// The eight ways the grid can be turned and flipped over: the four
+    /// rotations clockwise, starting with the grid as it is, then the same
+    /// four of the grid flipped left to right
+    pub fn symmetries(&self) -> impl Iterator<Item = Grid<T>> + '_ {
+        (0..8).map(move |n| {
+            let mut grid = if n < 4 { self.clone() } else { self.flip_h() };
+            for _ in 0..n % 4 {
+                grid = grid.rotate_cw();
+            }
+            grid
+        })
+    }
+}
+
+#[test]
+fn transformations() {
+    let grid = "abc\ndef".parse::<Grid<char>>().unwrap();
+    assert_eq!(grid.transpose().to_string(), "ad\nbe\ncf");
+    assert_eq!(grid.rotate_cw().to_string(), "da\neb\nfc");
+    assert_eq!(grid.rotate_ccw().to_string(), "cf\nbe\nad");
+    assert_eq!(grid.flip_h().to_string(), "cba\nfed");
+    assert_eq!(grid.flip_v().to_string(), "def\nabc");
+    assert_eq!(
+        grid.rotate_cw().rotate_cw().to_string(),
+        grid.flip_h().flip_v().to_string()
+    );
+    assert_eq!(grid.pad(1, '.').to_string(), ".....\n.abc.\n.def.\n.....");
+
+    let corner = |x, y| crate::Coord::new(x, y);
+    let bounds = BoundingBox::from_points(vec![corner(1, 0), corner(2, 1)]).unwrap();
+    assert_eq!(grid.crop(bounds).to_string(), "bc\nef");
+    let padded = BoundingBox::from_points(vec![corner(3, 2), corner(4, 3)]).unwrap();
+    assert_eq!(grid.pad(2, '.').crop(padded).to_string(), "bc\nef");
+
+    let all = grid.symmetries().map(|g| g.to_string()).collect::<Vec<_>>();
+    assert_eq!(all.len(), 8);
+    assert_eq!(all[0], grid.to_string());
+    assert_eq!(all[3], grid.rotate_ccw().to_string());
+    assert_eq!(all[4], grid.flip_h().to_string());
+    assert!(all.contains(&grid.transpose().to_string()));
+    assert!(all.contains(&grid.flip_v().to_string()));
+    let distinct = all.iter().collect::<std::collections::HashSet<_>>();
+    assert_eq!(distinct.len(), 8);
+}
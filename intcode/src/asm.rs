@@ -0,0 +1,929 @@
+//! A macro assembler for intcode.
+//!
+//! The assembler accepts the same syntax the disassembler prints, one
+//! statement per line:
+//!
+//! ```text
+//! loop:   add count, #-1, count   ; position, immediate and relative
+//!         jnz count, #loop        ; operands are written `n`, `#n` and
+//!         output rel(-1)          ; `rel(n)`
+//! count:  data 10
+//! ```
+//!
+//! Operands are expressions over numbers, labels and `$`, the address of the
+//! current statement, combined with `+`, `-` and parentheses. Besides
+//! instructions, a line may hold one of these directives:
+//!
+//! - `data a, b, ...` emits the values of its operands
+//! - `string "text"` emits the ASCII codes of `text` followed by a zero
+//! - `define name operand` replaces `name` with `operand` from then on
+//! - `macro name a, b ...` through `endm` defines a macro. Inside the body,
+//!   `\@` expands to a number that is unique to each expansion, to build
+//!   labels local to the macro
+//! - `include std` pulls in the standard library, [`STDLIB`]
+//! - `export a, b, ...` makes labels visible to other objects when assembled
+//!   with [`assemble_object`], and `extern a, b, ...` declares labels that
+//!   another object exports. Programs may export labels, which does nothing
+//!
+//! # Stack frames
+//!
+//! The relative base doubles as a stack pointer: the cell at `rel(0)` is the
+//! first free cell of a stack that grows upwards. These macros are always
+//! defined:
+//!
+//! - `push x` and `pop x` move a value on or off the stack. The destination
+//!   of `pop` is resolved after the stack has shrunk
+//! - `drop n` discards `n` values
+//! - `call f` pushes the return address and jumps to `f`, and `ret` or
+//!   `ret x` returns from it. This is the same convention interrupts use
+//! - `result x` stores the value returned by the function just called
+//! - `enter n` and `leave n` reserve and release `n` local variables, which
+//!   are then addressed as `rel(-n)` through `rel(-1)`
+//! - `jmp target` jumps unconditionally
+//!
+//! Arguments are pushed in order before `call`, so that inside the function
+//! the return address is at `rel(-1)`, the last argument at `rel(-2)`, and so
+//! on. `ret x` leaves `x` at `rel(1)` of the caller, who then drops the
+//! arguments:
+//!
+//! ```
+//! let program = intcode::assemble(
+//!     "       offset #stack
+//!             push #6
+//!             push #7
+//!             call mul
+//!             result answer
+//!             drop 2
+//!             output answer
+//!             halt
+//!
+//!      mul:   mul rel(-3), rel(-2), rel(0)
+//!             ret rel(0)
+//!
+//!      answer: data 0
+//!      stack:",
+//! )
+//! .unwrap();
+//! let mut vm = intcode::Vm::new(program);
+//! assert_eq!(vm.run(std::iter::empty(), false), Ok(42));
+//! ```
+use super::registry::BUILTIN_NAMES;
+use super::{builtin_params, Object, Param, Registry};
+use std::collections::HashMap;
+
+/// Routines available to programs that `include std`. See the comments in
+/// the source for their arguments
+pub const STDLIB: &str = include_str!("std.asm");
+
+/// Macros implementing the stack frame conventions, defined for every program
+const PRELUDE: &str = r"
+macro push x
+    add x, #0, rel(0)
+    offset #1
+endm
+
+macro pop x
+    offset #-1
+    add rel(0), #0, x
+endm
+
+macro drop n
+    offset #-(n)
+endm
+
+macro enter n
+    offset #n
+endm
+
+macro leave n
+    offset #-(n)
+endm
+
+macro jmp target
+    jnz #1, #target
+endm
+
+macro call target
+    add #ret\@, #0, rel(0)
+    offset #1
+    jmp target
+ret\@:
+endm
+
+macro ret
+    offset #-1
+    jz #0, rel(0)
+endm
+
+macro ret value
+    add value, #0, rel(0)
+    ret
+endm
+
+macro result x
+    add rel(1), #0, x
+endm
+";
+
+/// Macros may expand to other macros, up to this depth
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    /// Line of the source the error was found on, starting from 1
+    pub line: usize,
+    pub msg: String,
+}
+
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+fn error<T>(line: usize, msg: String) -> Result<T, AsmError> {
+    Err(AsmError { line, msg })
+}
+
+/// Assemble a program that only uses the built-in instructions
+pub fn assemble(source: &str) -> Result<Vec<isize>, AsmError> {
+    assemble_with(source, &Registry::new())
+}
+
+/// Assemble a program, resolving mnemonics that aren't built in through
+/// `registry`
+pub fn assemble_with(source: &str, registry: &Registry) -> Result<Vec<isize>, AsmError> {
+    let mut expander = Expander::default();
+    expander.expand(&numbered(PRELUDE), 0)?;
+    expander.expand(&numbered(source), 0)?;
+    Ok(Assembly::new(registry, false).build(&expander.out)?.code)
+}
+
+/// Assemble a module into a relocatable [`Object`], to be combined with
+/// others by [`link`](super::link)
+pub fn assemble_object(source: &str) -> Result<Object, AsmError> {
+    let mut expander = Expander::default();
+    expander.expand(&numbered(PRELUDE), 0)?;
+    expander.expand(&numbered(source), 0)?;
+    Assembly::new(&Registry::new(), true).build(&expander.out)
+}
+
+/// Lines of `source`, each paired with its line number
+fn numbered(source: &str) -> Vec<(usize, String)> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(n, text)| (n + 1, text.to_string()))
+        .collect()
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn valid_ident(s: &str) -> bool {
+    s.starts_with(is_ident_start) && s.chars().all(is_ident)
+}
+
+/// Remove a trailing `;` comment, ignoring semicolons in string literals
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Replace every identifier in `text` that is a key of `names`, leaving
+/// string literals alone. `\@` is replaced with `unique`
+fn substitute(text: &str, names: &HashMap<String, String>, unique: Option<usize>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.char_indices().peekable();
+    let mut quoted = false;
+    while let Some((i, c)) = chars.next() {
+        if quoted {
+            out.push(c);
+            if c == '\\' {
+                out.extend(chars.next().map(|(_, c)| c));
+            } else if c == '"' {
+                quoted = false;
+            }
+        } else if c == '"' {
+            quoted = true;
+            out.push(c);
+        } else if let (Some(n), '\\', Some((_, '@'))) = (unique, c, chars.peek()) {
+            chars.next();
+            out.push_str(&format!("__{}", n));
+        } else if is_ident_start(c) {
+            let mut end = i + c.len_utf8();
+            while let Some(&(j, c)) = chars.peek() {
+                if !is_ident(c) {
+                    break;
+                }
+                end = j + c.len_utf8();
+                chars.next();
+            }
+            let ident = &text[i..end];
+            out.push_str(names.get(ident).map(String::as_str).unwrap_or(ident));
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Split a list of operands on commas that aren't nested in parentheses
+fn split_operands(text: &str) -> Vec<&str> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut out = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                out.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    out.push(text[start..].trim());
+    out
+}
+
+/// Index of the parenthesis closing the one `text` starts with
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Split a statement into its leading word and the rest of the line
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    }
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<(usize, String)>,
+}
+
+/// First pass: expands macros, defines and includes into a flat list of
+/// labels and statements, each tagged with the source line it came from
+#[derive(Default)]
+struct Expander {
+    macros: HashMap<(String, usize), Macro>,
+    defines: HashMap<String, String>,
+    expansions: usize,
+    out: Vec<(usize, String)>,
+}
+
+impl Expander {
+    fn expand(&mut self, lines: &[(usize, String)], depth: usize) -> Result<(), AsmError> {
+        let mut lines = lines.iter();
+        while let Some((line, text)) = lines.next() {
+            let line = *line;
+            let mut text = strip_comment(text).trim();
+
+            // Labels, possibly followed by a statement on the same line
+            while let Some(colon) = text.find(':') {
+                let label = text[..colon].trim();
+                if !valid_ident(label) {
+                    break;
+                }
+                self.out.push((line, format!("{}:", label)));
+                text = text[colon + 1..].trim();
+            }
+            if text.is_empty() {
+                continue;
+            }
+
+            let (word, rest) = split_word(text);
+            match word {
+                "macro" => {
+                    let (name, params) = split_word(rest);
+                    if !valid_ident(name) {
+                        return error(line, format!("invalid macro name '{}'", name));
+                    }
+                    let params = split_operands(params)
+                        .into_iter()
+                        .map(str::to_string)
+                        .collect::<Vec<_>>();
+                    if let Some(p) = params.iter().find(|p| !valid_ident(p)) {
+                        return error(line, format!("invalid macro parameter '{}'", p));
+                    }
+                    let mut body = Vec::new();
+                    loop {
+                        match lines.next() {
+                            Some((_, t)) if strip_comment(t).trim() == "endm" => break,
+                            Some((l, t)) => body.push((*l, t.clone())),
+                            None => return error(line, format!("macro {} has no endm", name)),
+                        }
+                    }
+                    self.macros
+                        .insert((name.to_string(), params.len()), Macro { params, body });
+                }
+                "endm" => return error(line, "endm without macro".to_string()),
+                "include" if rest == "std" => {
+                    // Errors in the library are reported at the include
+                    let lib = STDLIB.lines().map(|t| (line, t.to_string()));
+                    self.expand(&lib.collect::<Vec<_>>(), depth)?;
+                }
+                "include" => return error(line, format!("unknown library '{}'", rest)),
+                "define" => {
+                    let (name, value) = split_word(rest);
+                    if !valid_ident(name) || value.is_empty() {
+                        return error(line, "expected define name value".to_string());
+                    }
+                    let value = substitute(value, &self.defines, None);
+                    self.defines.insert(name.to_string(), value);
+                }
+                _ => {
+                    let rest = substitute(rest, &self.defines, None);
+                    let args = split_operands(&rest);
+                    let key = (word.to_string(), args.len());
+                    let mac = match self.macros.get(&key) {
+                        Some(mac) => mac,
+                        None => {
+                            self.out.push((line, format!("{} {}", word, rest)));
+                            continue;
+                        }
+                    };
+                    if depth == MAX_DEPTH {
+                        return error(line, format!("macro {} expands too deeply", word));
+                    }
+                    let names = mac
+                        .params
+                        .iter()
+                        .cloned()
+                        .zip(args.iter().map(|a| a.to_string()))
+                        .collect::<HashMap<_, _>>();
+                    self.expansions += 1;
+                    // Expanded lines are reported at the line of the invocation
+                    let body = mac
+                        .body
+                        .iter()
+                        .map(|(_, t)| (line, substitute(t, &names, Some(self.expansions))))
+                        .collect::<Vec<_>>();
+                    self.expand(&body, depth + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+enum Statement<'a> {
+    Instr {
+        code: isize,
+        params: Vec<Param>,
+        operands: Vec<&'a str>,
+    },
+    Data(Vec<&'a str>),
+    Str(Vec<isize>),
+}
+
+impl Statement<'_> {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instr { params, .. } => 1 + params.len(),
+            Statement::Data(values) => values.len(),
+            Statement::Str(chars) => chars.len(),
+        }
+    }
+}
+
+/// Parse the body of a `string` directive, returning the characters followed
+/// by the terminating zero
+fn parse_string(text: &str) -> Option<Vec<isize>> {
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                '0' => '\0',
+                c @ ('\\' | '"') => c,
+                _ => return None,
+            },
+            '"' => return None,
+            c => c,
+        };
+        out.push(c as isize);
+    }
+    out.push(0);
+    Some(out)
+}
+
+/// An evaluated operand
+#[derive(Default)]
+struct Value<'a> {
+    n: isize,
+    /// How many times the address the code is loaded at is included in `n`
+    relative: isize,
+    /// Symbol from another object whose address is to be added to `n`
+    import: Option<&'a str>,
+}
+
+/// Second pass: lays statements out in memory and resolves labels
+struct Assembly<'r> {
+    registry: &'r Registry,
+    labels: HashMap<String, usize>,
+    /// Symbols declared `extern`, with the line they were declared on
+    externs: HashMap<String, usize>,
+    /// Set when building a relocatable object rather than a program
+    object: bool,
+}
+
+impl<'r> Assembly<'r> {
+    fn new(registry: &'r Registry, object: bool) -> Assembly<'r> {
+        Assembly {
+            registry,
+            labels: HashMap::new(),
+            externs: HashMap::new(),
+            object,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<(isize, Vec<Param>)> {
+        if let Some(i) = BUILTIN_NAMES.iter().position(|&n| n == name) {
+            let code = if name == "halt" { 99 } else { i as isize + 1 };
+            return builtin_params(code).map(|p| (code, p.to_vec()));
+        }
+        let custom = self.registry.by_name(name)?;
+        Some((custom.code, custom.params.clone()))
+    }
+
+    fn parse<'a>(&self, line: usize, text: &'a str) -> Result<Statement<'a>, AsmError> {
+        let (word, rest) = split_word(text);
+        match word {
+            "data" => Ok(Statement::Data(split_operands(rest))),
+            "string" => match parse_string(rest) {
+                Some(chars) => Ok(Statement::Str(chars)),
+                None => error(line, format!("invalid string {}", rest)),
+            },
+            _ => {
+                let (code, params) = match self.lookup(word) {
+                    Some(instr) => instr,
+                    None => return error(line, format!("unknown instruction '{}'", word)),
+                };
+                let operands = split_operands(rest);
+                if operands.len() != params.len() {
+                    return error(
+                        line,
+                        format!(
+                            "{} takes {} operand(s), found {}",
+                            word,
+                            params.len(),
+                            operands.len()
+                        ),
+                    );
+                }
+                Ok(Statement::Instr {
+                    code,
+                    params,
+                    operands,
+                })
+            }
+        }
+    }
+
+    /// Evaluate a sum of numbers, labels, `$` and parenthesized sums
+    fn eval<'a>(&self, line: usize, here: usize, expr: &'a str) -> Result<Value<'a>, AsmError> {
+        let total = self.sum(line, here, expr)?;
+        // An address plus a distance between addresses is fine, but an object
+        // can't be patched with anything else
+        let relocatable = match total.import {
+            Some(_) => total.relative == 0,
+            None => total.relative == 0 || total.relative == 1,
+        };
+        if self.object && !relocatable {
+            return error(line, format!("cannot relocate '{}'", expr.trim()));
+        }
+        Ok(total)
+    }
+
+    fn sum<'a>(&self, line: usize, here: usize, expr: &'a str) -> Result<Value<'a>, AsmError> {
+        let mut total = Value::default();
+        let mut rest = expr.trim();
+        if rest.is_empty() {
+            return error(line, "missing operand".to_string());
+        }
+        while !rest.is_empty() {
+            let mut sign = 1;
+            while let Some(r) = rest.strip_prefix(['+', '-']) {
+                if rest.starts_with('-') {
+                    sign = -sign;
+                }
+                rest = r.trim_start();
+            }
+            let end = if rest.starts_with('(') {
+                match closing_paren(rest) {
+                    Some(i) => i + 1,
+                    None => return error(line, format!("unbalanced parentheses in '{}'", expr)),
+                }
+            } else {
+                rest.find(|c: char| c == '+' || c == '-' || c.is_whitespace())
+                    .unwrap_or(rest.len())
+            };
+            let term = &rest[..end];
+            let value = if let Some(inner) = term.strip_prefix('(') {
+                let inner = self.sum(line, here, &inner[..inner.len() - 1])?;
+                if inner.import.is_some() && (sign < 0 || total.import.is_some()) {
+                    return error(line, format!("cannot relocate '{}'", expr.trim()));
+                }
+                total.import = total.import.or(inner.import);
+                total.relative += sign * inner.relative;
+                inner.n
+            } else if term == "$" {
+                total.relative += sign;
+                here as isize
+            } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+                match term.parse::<isize>() {
+                    Ok(x) => x,
+                    Err(_) => return error(line, format!("invalid number '{}'", term)),
+                }
+            } else if self.externs.contains_key(term) {
+                if sign < 0 || total.import.is_some() {
+                    return error(line, format!("cannot relocate '{}'", expr.trim()));
+                }
+                total.import = Some(term);
+                0
+            } else if valid_ident(term) {
+                match self.labels.get(term) {
+                    Some(&addr) => {
+                        total.relative += sign;
+                        addr as isize
+                    }
+                    None => return error(line, format!("undefined label '{}'", term)),
+                }
+            } else {
+                return error(line, format!("invalid operand '{}'", expr));
+            };
+            total.n = total.n.wrapping_add(sign * value);
+            rest = rest[end..].trim_start();
+            if !rest.is_empty() && !rest.starts_with(['+', '-']) {
+                return error(line, format!("invalid operand '{}'", expr));
+            }
+        }
+        Ok(total)
+    }
+
+    /// Encode a single operand, returning its mode digit and value
+    fn operand<'a>(
+        &self,
+        line: usize,
+        here: usize,
+        param: Param,
+        text: &'a str,
+    ) -> Result<(isize, Value<'a>), AsmError> {
+        if let Some(imm) = text.strip_prefix('#') {
+            if param == Param::Write {
+                return error(line, format!("cannot write to immediate {}", text));
+            }
+            return Ok((1, self.eval(line, here, imm)?));
+        }
+        if let Some(off) = text.strip_prefix("rel(").and_then(|t| t.strip_suffix(')')) {
+            return Ok((2, self.eval(line, here, off)?));
+        }
+        let addr = self.eval(line, here, text)?;
+        if addr.n < 0 {
+            return error(line, format!("negative address {}", text));
+        }
+        Ok((0, addr))
+    }
+
+    fn build(mut self, lines: &[(usize, String)]) -> Result<Object, AsmError> {
+        let mut statements = Vec::new();
+        let mut exports = Vec::new();
+        let mut addr = 0;
+        for (line, text) in lines {
+            if let Some(label) = text.strip_suffix(':') {
+                if self.labels.insert(label.to_string(), addr).is_some() {
+                    return error(*line, format!("label '{}' is already defined", label));
+                }
+                continue;
+            }
+            let (word, rest) = split_word(text);
+            if word == "export" || word == "extern" {
+                for name in split_operands(rest) {
+                    if !valid_ident(name) {
+                        return error(*line, format!("invalid symbol '{}'", name));
+                    }
+                    if word == "export" {
+                        exports.push((*line, name.to_string()));
+                    } else if self.object {
+                        self.externs.insert(name.to_string(), *line);
+                    } else {
+                        return error(*line, format!("extern '{}' needs an object", name));
+                    }
+                }
+                continue;
+            }
+            let stmt = self.parse(*line, text)?;
+            addr += stmt.size();
+            statements.push((*line, stmt));
+        }
+
+        let mut object = Object::default();
+        for (line, name) in exports {
+            match self.labels.get(&name) {
+                Some(&addr) => object.exports.insert(name, addr),
+                None => return error(line, format!("undefined label '{}'", name)),
+            };
+        }
+        for (name, &line) in &self.externs {
+            if self.labels.contains_key(name) {
+                return error(line, format!("extern '{}' is also defined", name));
+            }
+        }
+
+        let Object {
+            code: out,
+            relocations,
+            imports,
+            ..
+        } = &mut object;
+        let mut emit = |out: &mut Vec<isize>, value: Value| {
+            if value.relative != 0 {
+                relocations.push(out.len());
+            }
+            if let Some(symbol) = value.import {
+                imports.push((out.len(), symbol.to_string()));
+            }
+            out.push(value.n);
+        };
+        for (line, stmt) in statements {
+            let here = out.len();
+            match stmt {
+                Statement::Instr {
+                    code,
+                    params,
+                    operands,
+                } => {
+                    out.push(code);
+                    for (n, (param, text)) in params.into_iter().zip(operands).enumerate() {
+                        let (mode, value) = self.operand(line, here, param, text)?;
+                        out[here] += mode * 10isize.pow(n as u32 + 2);
+                        emit(out, value);
+                    }
+                }
+                Statement::Data(values) => {
+                    for v in values {
+                        emit(out, self.eval(line, here, v)?);
+                    }
+                }
+                Statement::Str(chars) => out.extend(chars),
+            }
+        }
+        Ok(object)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Error, Vm};
+    use std::sync::Arc;
+
+    fn outputs(program: Vec<isize>, input: &[isize]) -> Vec<isize> {
+        let mut vm = Vm::new(program);
+        let mut input = input.iter().copied();
+        let mut out = Vec::new();
+        loop {
+            match vm.run(&mut input, false) {
+                Ok(x) => out.push(x),
+                Err(Error::Halted) => return out,
+                Err(e) => panic!("{:?} at ip {}", e, vm.ip),
+            }
+        }
+    }
+
+    fn ascii(program: &str) -> String {
+        let program = assemble(program).unwrap();
+        outputs(program, &[])
+            .into_iter()
+            .map(|c| c as u8 as char)
+            .collect()
+    }
+
+    #[test]
+    fn instructions() {
+        let program = assemble(
+            "start:  input n            ; read n
+                     mul n, #3, rel(-1)
+                     jz #0, #start+9
+                     halt
+             n:      data 0, n, $",
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            vec![3, 10, 21002, 10, 3, -1, 1106, 0, 9, 99, 0, 10, 10]
+        );
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        let quine = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        let vm = quine.parse::<Vm>().unwrap();
+        let source = vm
+            .disassemble()
+            .iter()
+            .map(|(_, op)| op.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(assemble(&source).unwrap(), vm.data);
+    }
+
+    #[test]
+    fn macros_and_defines() {
+        let program = assemble(
+            "macro countdown from
+                 add #from, #0, counter
+             loop\\@:
+                 output counter
+                 add counter, #-1, counter
+                 jnz counter, #loop\\@
+             endm
+
+             define counter cell
+                 countdown 3
+                 countdown 2
+                 halt
+             cell: data 0",
+        )
+        .unwrap();
+        assert_eq!(outputs(program, &[]), vec![3, 2, 1, 2, 1]);
+    }
+
+    #[test]
+    fn stack_frames() {
+        // Recursive factorial, with n kept in a local variable
+        let program = assemble(
+            "        offset #stack
+                     input rel(0)
+                     push rel(0)
+                     call fact
+                     result rel(0)
+                     drop 1
+                     output rel(1)
+                     halt
+
+             fact:   enter 1
+                     add rel(-3), #0, rel(-1)
+                     jnz rel(-1), #recurse
+                     leave 1
+                     ret #1
+             recurse:
+                     add rel(-1), #-1, rel(0)
+                     push rel(0)
+                     call fact
+                     mul rel(1), rel(-2), rel(1)
+                     drop 1
+                     leave 1
+                     ret rel(3)
+             stack:",
+        )
+        .unwrap();
+        assert_eq!(outputs(program.clone(), &[0]), vec![1]);
+        assert_eq!(outputs(program, &[10]), vec![3628800]);
+    }
+
+    #[test]
+    fn expression_arguments() {
+        let program = assemble(
+            "define two 2
+             drop two + 1
+             leave 3 - 1
+             data -(two + 3), 10 - (two - (1 + 1)), ((4))",
+        )
+        .unwrap();
+        assert_eq!(program, vec![109, -3, 109, -2, -5, 10, 4]);
+        assert_eq!(
+            assemble("data (1 + 2").unwrap_err().to_string(),
+            "line 1: unbalanced parentheses in '(1 + 2'"
+        );
+    }
+
+    #[test]
+    fn stdlib() {
+        assert_eq!(
+            ascii(
+                "       offset #stack
+                        push #label
+                        call std_print_str
+                        push #-1234
+                        call std_print_num
+                        push #5041
+                        call std_print_num
+                        halt
+                 label: string \"n = \"
+                 include std
+                 stack:"
+            ),
+            "n = -12345041"
+        );
+
+        let program = assemble(
+            "       offset #stack
+                    input rel(0)
+                    input rel(1)
+                    offset #2
+                    call std_div
+                    result div
+                    call std_mod
+                    result mod
+                    output div
+                    output mod
+                    push #a
+                    push #b
+                    push #3
+                    call std_dot
+                    result rel(0)
+                    output rel(0)
+                    halt
+             a:     data 1, 2, 3
+             b:     data 4, 5, 6
+             div:   data 0
+             mod:   data 0
+             include std
+             stack:",
+        )
+        .unwrap();
+        assert_eq!(outputs(program.clone(), &[47, 5]), vec![9, 2, 32]);
+        assert_eq!(outputs(program, &[3, 7]), vec![0, 3, 32]);
+    }
+
+    #[test]
+    fn custom_instructions() {
+        let mut registry = Registry::new();
+        registry.register(
+            "div",
+            10,
+            &[Param::Read, Param::Read, Param::Write],
+            |vm, p| {
+                let a = vm.fetch(p[0])?;
+                let b = vm.fetch(p[1])?;
+                vm.store_or_extend(p[2], a / b)?;
+                Ok(None)
+            },
+        );
+        let program = assemble_with("div #84, #4, 0\noutput 0\nhalt", &registry).unwrap();
+        assert_eq!(program, vec![1110, 84, 4, 0, 4, 0, 99]);
+        let mut vm = Vm::new(program);
+        vm.set_registry(Arc::new(registry));
+        assert_eq!(vm.run(std::iter::empty(), false), Ok(21));
+    }
+
+    #[test]
+    fn errors() {
+        let err = |source: &str| assemble(source).unwrap_err().to_string();
+        assert_eq!(err("halt\nfoo 1"), "line 2: unknown instruction 'foo'");
+        assert_eq!(err("add 1, 2"), "line 1: add takes 3 operand(s), found 2");
+        assert_eq!(err("\n\ninput #3"), "line 3: cannot write to immediate #3");
+        assert_eq!(err("jmp nowhere"), "line 1: undefined label 'nowhere'");
+        assert_eq!(
+            err("a: halt\na: halt"),
+            "line 2: label 'a' is already defined"
+        );
+        assert_eq!(err("output -1"), "line 1: negative address -1");
+        assert_eq!(err("macro m\nhalt"), "line 1: macro m has no endm");
+        assert_eq!(
+            err("macro m\nm\nendm\nm"),
+            "line 4: macro m expands too deeply"
+        );
+    }
+}
@@ -0,0 +1,413 @@
+//! Running machines as futures.
+//!
+//! [`AsyncVm::run`] awaits input from a [`Source`] and pushes outputs into a
+//! [`Sink`], and [`AsyncVm::next_output`] stops at each output like
+//! [`Vm::run`], so machines can be combined with each other and with other
+//! asynchronous events on a single thread. It is built on [`Vm::step`] like
+//! the synchronous engines: an input instruction that finds no input leaves
+//! the machine where it was, and the future waits until the source is ready
+//! to try again.
+//!
+//! There are no dependencies on an async runtime. [`Executor`] runs any
+//! number of futures on the current thread, [`channel`] connects machines to
+//! each other, and [`sleep`] waits for a while on a helper thread.
+//!
+//! ```
+//! use intcode::{channel, AsyncVm, Executor, Vm};
+//!
+//! // Adds one to each input, until the input is closed
+//! let inc = "3,11,1001,11,1,11,4,11,1105,1,0,0".parse::<Vm>().unwrap();
+//! let (to_first, from_main) = channel();
+//! let (to_second, from_first) = channel();
+//! let (to_main, mut from_second) = channel();
+//!
+//! let mut executor = Executor::new();
+//! for (mut input, mut output) in vec![(from_main, to_second), (from_first, to_main)] {
+//!     let mut vm = AsyncVm::new(inc.clone());
+//!     executor.spawn(async move {
+//!         let _ = vm.run(&mut input, &mut output).await;
+//!     });
+//! }
+//! for x in &[1, 2, 3] {
+//!     to_first.send(*x);
+//! }
+//! drop(to_first);
+//! executor.run();
+//! assert_eq!(from_second.drain(), vec![3, 4, 5]);
+//! ```
+use super::{Error, Step, Vm};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+/// Instructions executed before a running machine lets other futures run
+const BUDGET: usize = 1000;
+
+/// Asynchronous source of input values
+pub trait Source {
+    /// Ready with `None` once there will be no more input
+    fn poll_input(&mut self, cx: &mut Context) -> Poll<Option<isize>>;
+}
+
+/// Destination for output values
+pub trait Sink {
+    fn output(&mut self, x: isize);
+}
+
+/// Iterators are sources that are always ready
+impl<I: Iterator<Item = isize>> Source for I {
+    fn poll_input(&mut self, _: &mut Context) -> Poll<Option<isize>> {
+        Poll::Ready(self.next())
+    }
+}
+
+impl Sink for Vec<isize> {
+    fn output(&mut self, x: isize) {
+        self.push(x)
+    }
+}
+
+impl<F: FnMut(isize)> Sink for F {
+    fn output(&mut self, x: isize) {
+        self(x)
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: VecDeque<isize>,
+    waker: Option<Waker>,
+    senders: usize,
+}
+
+/// Sending half of a [`channel`]. The channel is closed once every sender
+/// is dropped
+pub struct Sender(Rc<RefCell<Shared>>);
+
+/// Receiving half of a [`channel`]
+pub struct Receiver(Rc<RefCell<Shared>>);
+
+/// An unbounded queue of values for futures on the same thread
+pub fn channel() -> (Sender, Receiver) {
+    let shared = Rc::new(RefCell::new(Shared {
+        senders: 1,
+        ..Shared::default()
+    }));
+    (Sender(shared.clone()), Receiver(shared))
+}
+
+impl Sender {
+    pub fn send(&self, x: isize) {
+        let mut shared = self.0.borrow_mut();
+        shared.queue.push_back(x);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Sender {
+        self.0.borrow_mut().senders += 1;
+        Sender(self.0.clone())
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        let mut shared = self.0.borrow_mut();
+        shared.senders -= 1;
+        if shared.senders == 0 {
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Sink for Sender {
+    fn output(&mut self, x: isize) {
+        self.send(x)
+    }
+}
+
+impl Receiver {
+    /// Take every value that has been sent so far
+    pub fn drain(&mut self) -> Vec<isize> {
+        self.0.borrow_mut().queue.drain(..).collect()
+    }
+}
+
+impl Source for Receiver {
+    fn poll_input(&mut self, cx: &mut Context) -> Poll<Option<isize>> {
+        let mut shared = self.0.borrow_mut();
+        match shared.queue.pop_front() {
+            Some(x) => Poll::Ready(Some(x)),
+            None if shared.senders == 0 => Poll::Ready(None),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Wait for `duration` without blocking the executor
+pub async fn sleep(duration: Duration) {
+    let done = Arc::new(Mutex::new((false, None::<Waker>)));
+    let timer = done.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(duration);
+        let mut done = timer.lock().unwrap();
+        done.0 = true;
+        if let Some(waker) = done.1.take() {
+            waker.wake();
+        }
+    });
+    std::future::poll_fn(|cx| {
+        let mut done = done.lock().unwrap();
+        if done.0 {
+            Poll::Ready(())
+        } else {
+            done.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// A machine that runs as a future
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsyncVm {
+    pub vm: Vm,
+}
+
+impl AsyncVm {
+    pub fn new(vm: Vm) -> AsyncVm {
+        AsyncVm { vm }
+    }
+
+    /// Run until the machine halts, awaiting each input from `input` and
+    /// passing every output to `output`. Fails with [`Error::NoInput`] if the
+    /// source runs dry
+    pub async fn run<S, K>(&mut self, input: &mut S, output: &mut K) -> Result<(), Error>
+    where
+        S: Source + ?Sized,
+        K: Sink + ?Sized,
+    {
+        loop {
+            match self.next_output(input).await {
+                Ok(x) => output.output(x),
+                Err(Error::Halted) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Run until the machine produces an output, awaiting each input from
+    /// `input`. Fails with [`Error::Halted`] once the machine halts
+    pub async fn next_output<S: Source + ?Sized>(&mut self, input: &mut S) -> Result<isize, Error> {
+        let vm = &mut self.vm;
+        std::future::poll_fn(|cx| {
+            for _ in 0..BUDGET {
+                let mut pending = false;
+                let step = vm.step(
+                    || match input.poll_input(cx) {
+                        Poll::Ready(x) => x,
+                        Poll::Pending => {
+                            pending = true;
+                            None
+                        }
+                    },
+                    false,
+                );
+                match step {
+                    Ok(Step::Output(x)) => return Poll::Ready(Ok(x)),
+                    Ok(Step::Continue) => {}
+                    Err(Error::NoInput) if pending => return Poll::Pending,
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            }
+            // Give everything else a turn before carrying on
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Tasks woken since they were last polled
+#[derive(Default)]
+struct Ready {
+    tasks: Mutex<VecDeque<usize>>,
+    signal: Condvar,
+}
+
+struct TaskWaker {
+    id: usize,
+    ready: Arc<Ready>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.ready.tasks.lock().unwrap().push_back(self.id);
+        self.ready.signal.notify_one();
+    }
+}
+
+/// Runs futures on the current thread
+#[derive(Default)]
+pub struct Executor {
+    tasks: Vec<Option<Pin<Box<dyn Future<Output = ()>>>>>,
+    ready: Arc<Ready>,
+}
+
+impl Executor {
+    pub fn new() -> Executor {
+        Executor::default()
+    }
+
+    pub fn spawn<F: Future<Output = ()> + 'static>(&mut self, future: F) {
+        self.ready.tasks.lock().unwrap().push_back(self.tasks.len());
+        self.tasks.push(Some(Box::pin(future)));
+    }
+
+    /// Number of tasks that haven't finished
+    pub fn pending(&self) -> usize {
+        self.tasks.iter().filter(|t| t.is_some()).count()
+    }
+
+    fn poll(&mut self, id: usize) {
+        let waker = Waker::from(Arc::new(TaskWaker {
+            id,
+            ready: self.ready.clone(),
+        }));
+        if let Some(task) = &mut self.tasks[id] {
+            if task
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_ready()
+            {
+                self.tasks[id] = None;
+            }
+        }
+    }
+
+    /// Poll tasks until none of them can make progress without something
+    /// from outside the executor, such as a timer. Returns whether every
+    /// task has finished
+    pub fn run_until_stalled(&mut self) -> bool {
+        loop {
+            let next = self.ready.tasks.lock().unwrap().pop_front();
+            match next {
+                Some(id) => self.poll(id),
+                None => return self.pending() == 0,
+            }
+        }
+    }
+
+    /// Run every task to completion, blocking the thread while they wait on
+    /// outside events. Tasks that wait on each other forever never finish,
+    /// so use [`run_until_stalled`](Executor::run_until_stalled) to detect
+    /// a deadlock instead
+    pub fn run(&mut self) {
+        while !self.run_until_stalled() {
+            let tasks = self.ready.tasks.lock().unwrap();
+            drop(self.ready.signal.wait_while(tasks, |t| t.is_empty()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn feedback_loop() {
+        let vm = "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,
+                  27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5"
+            .parse::<Vm>()
+            .unwrap();
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..5).map(|_| channel()).unzip();
+        for (tx, phase) in senders.iter().zip(&[9, 8, 7, 6, 5]) {
+            tx.send(*phase);
+        }
+        senders[0].send(0);
+
+        let signal = Rc::new(std::cell::Cell::new(0));
+        let mut executor = Executor::new();
+        let outputs = senders.into_iter().cycle().skip(1);
+        for (mut input, next) in receivers.into_iter().zip(outputs) {
+            let signal = signal.clone();
+            let mut output = move |x| {
+                signal.set(x);
+                next.send(x);
+            };
+            let mut vm = AsyncVm::new(vm.clone());
+            executor.spawn(async move {
+                assert_eq!(vm.run(&mut input, &mut output).await, Ok(()));
+            });
+        }
+        assert!(executor.run_until_stalled());
+        assert_eq!(signal.get(), 139629729);
+    }
+
+    #[test]
+    fn starved_and_deadlocked() {
+        let echo = "3,5,4,5,99,0".parse::<Vm>().unwrap();
+        let mut vm = AsyncVm::new(echo.clone());
+        let mut executor = Executor::new();
+        executor.spawn(async move {
+            let result = vm.run(&mut std::iter::empty(), &mut Vec::new()).await;
+            assert_eq!(result, Err(Error::NoInput));
+        });
+        assert!(executor.run_until_stalled());
+
+        // Two machines waiting on each other never finish
+        let (a_tx, a_rx) = channel();
+        let (b_tx, b_rx) = channel();
+        let mut executor = Executor::new();
+        for (mut input, mut output) in [(a_rx, b_tx), (b_rx, a_tx)] {
+            let mut vm = AsyncVm::new(echo.clone());
+            executor.spawn(async move { vm.run(&mut input, &mut output).await.unwrap() });
+        }
+        assert!(!executor.run_until_stalled());
+        assert_eq!(executor.pending(), 2);
+    }
+
+    #[test]
+    fn timers() {
+        // Input trickles in from a timer while another machine keeps busy
+        let (tx, mut rx) = channel();
+        let (mut output, mut received) = channel();
+        let mut executor = Executor::new();
+        executor.spawn(async move {
+            for x in 1..=3 {
+                sleep(Duration::from_millis(10)).await;
+                tx.send(x);
+            }
+        });
+        let mut echo = AsyncVm::new("3,7,4,7,1105,1,0,0".parse().unwrap());
+        executor.spawn(async move {
+            let result = echo.run(&mut rx, &mut output).await;
+            assert_eq!(result, Err(Error::NoInput));
+        });
+        let count = "1001,13,1,13,1007,13,100000,14,1005,14,0,99,0,0,0";
+        let mut count = AsyncVm::new(count.parse().unwrap());
+        executor.spawn(async move {
+            let result = count.run(&mut std::iter::empty(), &mut Vec::new()).await;
+            assert_eq!(result, Ok(()));
+        });
+
+        let start = Instant::now();
+        executor.run();
+        assert_eq!(received.drain(), vec![1, 2, 3]);
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+}
@@ -0,0 +1,191 @@
+//! Host an intcode program as a network service.
+//!
+//! Every connection gets its own copy of the machine, running in its own
+//! thread until the program halts, the client disconnects or the session
+//! reaches its instruction limit. The address
+//! being listened on is printed on startup, which is how to find the port
+//! chosen with `--tcp 127.0.0.1:0`. A socket file left behind by an earlier
+//! server at the `--unix` path is replaced.
+use intcode::{session, Error, Framing, Program, Vm};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::exit;
+use std::thread;
+
+const USAGE: &str = "usage: intcode-server [options] <program>
+
+options:
+    --tcp ADDR           listen on a TCP address (default 127.0.0.1:7777)
+    --unix PATH          listen on a Unix socket instead, replacing any file
+                         already at PATH
+    --ascii              exchange ASCII text rather than numbers, one per line
+    --set ADDR=VALUE     patch memory before running (repeatable)
+    --limit N            close a session after it executes N instructions
+                         (default 1000000000)";
+
+/// Instructions a session may execute unless `--limit` says otherwise
+const DEFAULT_LIMIT: u64 = 1_000_000_000;
+
+#[derive(Default)]
+struct Options {
+    program: Option<String>,
+    tcp: Option<String>,
+    unix: Option<String>,
+    ascii: bool,
+    patches: Vec<(usize, isize)>,
+    limit: Option<u64>,
+}
+
+fn usage(msg: &str) -> ! {
+    eprintln!("intcode-server: {}\n\n{}", msg, USAGE);
+    exit(1)
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Options {
+    let mut opts = Options::default();
+    let value = |args: &mut I, flag: &str| {
+        args.next()
+            .unwrap_or_else(|| usage(&format!("{} requires a value", flag)))
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0)
+            }
+            "--tcp" => opts.tcp = Some(value(&mut args, "--tcp")),
+            "--unix" => opts.unix = Some(value(&mut args, "--unix")),
+            "--ascii" => opts.ascii = true,
+            "--set" => {
+                let patch = value(&mut args, "--set");
+                match intcode::parse_patch(&patch) {
+                    Ok(patch) => opts.patches.push(patch),
+                    Err(Error::InvalidAddr(addr)) => {
+                        usage(&format!("patch address {} is out of range", addr))
+                    }
+                    Err(_) => usage(&format!("invalid patch {}", patch)),
+                }
+            }
+            "--limit" => {
+                let n = value(&mut args, "--limit");
+                opts.limit = Some(
+                    n.parse()
+                        .unwrap_or_else(|_| usage(&format!("invalid limit {}", n))),
+                );
+            }
+            _ if arg.starts_with('-') => usage(&format!("unknown option {}", arg)),
+            _ if opts.program.is_none() => opts.program = Some(arg),
+            _ => usage(&format!("unexpected argument {}", arg)),
+        }
+    }
+    if opts.tcp.is_some() && opts.unix.is_some() {
+        usage("--tcp and --unix are mutually exclusive");
+    }
+    opts
+}
+
+/// Accept connections forever, running a session of at most `limit`
+/// instructions for each on its own thread
+fn serve<S, I>(
+    incoming: I,
+    try_clone: fn(&S) -> io::Result<S>,
+    vm: &Vm,
+    framing: Framing,
+    limit: u64,
+) where
+    S: Read + Write + Send + 'static,
+    I: Iterator<Item = io::Result<S>>,
+{
+    for (id, stream) in incoming.enumerate() {
+        let (reader, writer) = match stream.and_then(|s| Ok((try_clone(&s)?, s))) {
+            Ok(halves) => halves,
+            Err(e) => {
+                eprintln!("intcode-server: {}", e);
+                continue;
+            }
+        };
+        let vm = vm.clone();
+        thread::spawn(move || {
+            match session(vm, BufReader::new(reader), writer, framing, Some(limit)) {
+                Ok(Some(stop)) => eprintln!("session {}: stopped with {:?}", id, stop),
+                Ok(None) => eprintln!("session {}: instruction limit reached", id),
+                Err(e) => eprintln!("session {}: {}", id, e),
+            }
+        });
+    }
+}
+
+fn main() {
+    let opts = parse_args(std::env::args().skip(1));
+    let path = opts
+        .program
+        .clone()
+        .unwrap_or_else(|| usage("no program given"));
+    let source = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("intcode-server: {}: {}", path, e);
+        exit(1)
+    });
+    let mut vm = Vm::from(source.parse::<Program>().unwrap_or_else(|e| {
+        eprintln!("intcode-server: {}: {}", path, e);
+        exit(1)
+    }));
+    for &(addr, val) in &opts.patches {
+        // Addresses were checked when the arguments were parsed
+        vm.patch(addr, val).expect("patch address out of range");
+    }
+    let framing = if opts.ascii {
+        Framing::Ascii
+    } else {
+        Framing::Numbers
+    };
+    let limit = opts.limit.unwrap_or(DEFAULT_LIMIT);
+
+    let failed = |e: io::Error| -> ! {
+        eprintln!("intcode-server: {}", e);
+        exit(1)
+    };
+
+    #[cfg(unix)]
+    {
+        if let Some(path) = &opts.unix {
+            use std::os::unix::net::{UnixListener, UnixStream};
+            // A server that didn't exit cleanly leaves its socket behind
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => failed(e),
+                _ => {}
+            }
+            let listener = UnixListener::bind(path).unwrap_or_else(|e| failed(e));
+            println!("listening on {}", path);
+            let _ = io::stdout().flush();
+            serve(
+                listener.incoming(),
+                UnixStream::try_clone,
+                &vm,
+                framing,
+                limit,
+            );
+            return;
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        if opts.unix.is_some() {
+            usage("Unix sockets are not supported on this platform");
+        }
+    }
+
+    let addr = opts.tcp.as_deref().unwrap_or("127.0.0.1:7777");
+    let listener = TcpListener::bind(addr).unwrap_or_else(|e| failed(e));
+    println!(
+        "listening on {}",
+        listener.local_addr().unwrap_or_else(|e| failed(e))
+    );
+    let _ = io::stdout().flush();
+    serve(
+        listener.incoming(),
+        TcpStream::try_clone,
+        &vm,
+        framing,
+        limit,
+    );
+}
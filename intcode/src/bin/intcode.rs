@@ -0,0 +1,370 @@
+//! Run an arbitrary intcode program from the command line.
+//!
+//! Exit status is 0 when the program halts, 2 when it is starved of input,
+//! 3 when it faults, 4 when the instruction limit is reached and 1 for usage
+//! errors. With `--replay`, it is 0 if the replayed execution matches the
+//! recording and 5 if it diverges.
+use intcode::{DecodeMode, Dump, Error, Program, Step, Trace, Vm};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, Write};
+use std::process::exit;
+
+const USAGE: &str = "usage: intcode [options] <program>
+
+options:
+    --set ADDR=VALUE     patch memory before running (repeatable)
+    --input VALUES       comma separated input values
+    --input-file PATH    read input from a file, or if PATH is -, from stdin
+                         a line at a time as the program asks for it
+    --ascii-in           treat input as ASCII text rather than numbers
+    --ascii-out          print outputs below 128 as ASCII characters
+    --trace              print each instruction to stderr as it executes
+    --profile            print instruction counts to stderr on exit
+    --limit N            stop after executing N instructions
+    --strict             reject malformed instructions
+    --record PATH        save an execution trace to PATH
+    --dump PATH          write a memory dump to PATH on exit, marking cells
+                         changed since the start; HTML if PATH ends in .html
+    --taint              report to stderr which inputs each output and
+                         branch depends on
+    --taint-cell ADDR    with --taint, count the initial value of a memory
+                         cell as an input too (repeatable)
+    --replay PATH        re-run against the inputs of a saved trace and report
+                         the first instruction where the executions differ";
+
+const EXIT_HALT: i32 = 0;
+const EXIT_USAGE: i32 = 1;
+const EXIT_STARVED: i32 = 2;
+const EXIT_FAULT: i32 = 3;
+const EXIT_LIMIT: i32 = 4;
+const EXIT_DIVERGED: i32 = 5;
+
+#[derive(Default)]
+struct Options {
+    program: Option<String>,
+    patches: Vec<(usize, isize)>,
+    input: Option<String>,
+    input_file: Option<String>,
+    ascii_in: bool,
+    ascii_out: bool,
+    trace: bool,
+    profile: bool,
+    limit: Option<u64>,
+    strict: bool,
+    record: Option<String>,
+    dump: Option<String>,
+    taint: bool,
+    taint_cells: Vec<usize>,
+    replay: Option<String>,
+}
+
+fn usage(msg: &str) -> ! {
+    eprintln!("intcode: {}\n\n{}", msg, USAGE);
+    exit(EXIT_USAGE)
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Options {
+    let mut opts = Options::default();
+    let value = |args: &mut I, flag: &str| {
+        args.next()
+            .unwrap_or_else(|| usage(&format!("{} requires a value", flag)))
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(EXIT_HALT)
+            }
+            "--set" => {
+                let patch = value(&mut args, "--set");
+                match intcode::parse_patch(&patch) {
+                    Ok(patch) => opts.patches.push(patch),
+                    Err(Error::InvalidAddr(addr)) => {
+                        usage(&format!("patch address {} is out of range", addr))
+                    }
+                    Err(_) => usage(&format!("invalid patch {}", patch)),
+                }
+            }
+            "--input" => opts.input = Some(value(&mut args, "--input")),
+            "--input-file" => opts.input_file = Some(value(&mut args, "--input-file")),
+            "--ascii-in" => opts.ascii_in = true,
+            "--ascii-out" => opts.ascii_out = true,
+            "--trace" => opts.trace = true,
+            "--profile" => opts.profile = true,
+            "--limit" => {
+                let n = value(&mut args, "--limit");
+                opts.limit = Some(
+                    n.parse()
+                        .unwrap_or_else(|_| usage(&format!("invalid limit {}", n))),
+                );
+            }
+            "--strict" => opts.strict = true,
+            "--record" => opts.record = Some(value(&mut args, "--record")),
+            "--dump" => opts.dump = Some(value(&mut args, "--dump")),
+            "--taint" => opts.taint = true,
+            "--taint-cell" => {
+                let addr = value(&mut args, "--taint-cell");
+                match addr.trim().parse() {
+                    Ok(addr) => opts.taint_cells.push(addr),
+                    Err(_) => usage(&format!("invalid address {}", addr)),
+                }
+            }
+            "--replay" => opts.replay = Some(value(&mut args, "--replay")),
+            _ if arg.starts_with('-') => usage(&format!("unknown option {}", arg)),
+            _ if opts.program.is_none() => opts.program = Some(arg),
+            _ => usage(&format!("unexpected argument {}", arg)),
+        }
+    }
+    opts
+}
+
+/// Parse `text` as input values, numbers separated by commas or whitespace
+/// or ASCII text, adding them to `values`
+fn parse_input(text: &str, ascii: bool, values: &mut VecDeque<isize>) -> io::Result<()> {
+    if ascii {
+        values.extend(text.bytes().map(|b| b as isize));
+        return Ok(());
+    }
+    for s in text.split(|c: char| c == ',' || c.is_whitespace()) {
+        if s.is_empty() {
+            continue;
+        }
+        values.push_back(s.parse::<isize>().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid input value {}", s),
+            )
+        })?);
+    }
+    Ok(())
+}
+
+/// Input values from `--input` followed by those from `--input-file`. Each
+/// source is parsed on its own, so a value never spans the two. Standard
+/// input is read a line at a time as the machine asks for it, so that an
+/// interactive program can be answered as it runs
+struct Input {
+    pending: VecDeque<isize>,
+    /// Standard input, until it is closed
+    stdin: Option<io::StdinLock<'static>>,
+    ascii: bool,
+    /// Why standard input stopped early, if it did
+    error: Option<io::Error>,
+}
+
+impl Input {
+    fn open(opts: &Options) -> io::Result<Input> {
+        let mut input = Input {
+            pending: VecDeque::new(),
+            stdin: None,
+            ascii: opts.ascii_in,
+            error: None,
+        };
+        if let Some(text) = &opts.input {
+            parse_input(text, input.ascii, &mut input.pending)?;
+        }
+        match opts.input_file.as_deref() {
+            Some("-") => input.stdin = Some(io::stdin().lock()),
+            Some(path) => {
+                let text = std::fs::read_to_string(path)?;
+                parse_input(&text, input.ascii, &mut input.pending)?;
+            }
+            None => {}
+        }
+        Ok(input)
+    }
+
+    /// The next value, reading another line from standard input once the
+    /// others run out. Output is flushed first, so that a prompt is seen
+    /// before waiting for the answer
+    fn next<W: Write>(&mut self, out: &mut W) -> Option<isize> {
+        while self.pending.is_empty() {
+            let stdin = self.stdin.as_mut()?;
+            let _ = out.flush();
+            let mut line = String::new();
+            let read = stdin
+                .read_line(&mut line)
+                .and_then(|n| parse_input(&line, self.ascii, &mut self.pending).map(|_| n));
+            match read {
+                Ok(0) => self.stdin = None,
+                Ok(_) => {}
+                Err(e) => {
+                    self.pending.clear();
+                    self.stdin = None;
+                    self.error = Some(e);
+                }
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+#[derive(Default)]
+struct Profile {
+    instructions: u64,
+    by_opcode: HashMap<&'static str, u64>,
+    by_addr: HashMap<usize, u64>,
+}
+
+impl Profile {
+    fn record(&mut self, vm: &Vm) {
+        self.instructions += 1;
+        if let Ok(op) = vm.decode(vm.ip) {
+            *self.by_opcode.entry(op.name()).or_insert(0) += 1;
+        }
+        *self.by_addr.entry(vm.ip).or_insert(0) += 1;
+    }
+
+    fn report(&self) {
+        eprintln!("{} instructions executed", self.instructions);
+        let mut ops = self.by_opcode.iter().collect::<Vec<_>>();
+        ops.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (name, n) in ops {
+            eprintln!("{:>10} {:>12}", name, n);
+        }
+        let mut hot = self.by_addr.iter().collect::<Vec<_>>();
+        hot.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        eprintln!("hottest addresses:");
+        for (addr, n) in hot.into_iter().take(10) {
+            eprintln!("{:>10} {:>12}", addr, n);
+        }
+    }
+}
+
+fn main() {
+    let opts = parse_args(std::env::args().skip(1));
+    let path = opts
+        .program
+        .clone()
+        .unwrap_or_else(|| usage("no program given"));
+
+    let source = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("intcode: {}: {}", path, e);
+        exit(EXIT_USAGE)
+    });
+    let mut vm = Vm::from(source.parse::<Program>().unwrap_or_else(|e| {
+        eprintln!("intcode: {}: {}", path, e);
+        exit(EXIT_USAGE)
+    }));
+    if opts.strict {
+        vm.set_decode_mode(DecodeMode::Strict);
+    }
+    for &(addr, val) in &opts.patches {
+        // Addresses were checked when the arguments were parsed
+        vm.patch(addr, val).expect("patch address out of range");
+    }
+    if let Some(path) = &opts.replay {
+        exit(replay(vm, path))
+    }
+    if opts.record.is_some() || opts.taint {
+        vm.start_trace();
+    }
+
+    let initial = opts.dump.as_ref().map(|_| vm.data.clone());
+
+    let mut input = Input::open(&opts).unwrap_or_else(|e| {
+        eprintln!("intcode: {}", e);
+        exit(EXIT_USAGE)
+    });
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let mut profile = Profile::default();
+    let mut executed = 0;
+
+    let status = loop {
+        if opts.limit.map(|n| executed >= n).unwrap_or(false) {
+            eprintln!("intcode: instruction limit reached at ip {}", vm.ip);
+            break EXIT_LIMIT;
+        }
+        if opts.trace {
+            match vm.decode(vm.ip) {
+                Ok(op) => eprintln!("{:5}: {:<32} base {}", vm.ip, op.to_string(), vm.base),
+                Err(_) => eprintln!("{:5}: ???", vm.ip),
+            }
+        }
+        if opts.profile {
+            profile.record(&vm);
+        }
+        executed += 1;
+
+        let ip = vm.ip;
+        match vm.step(|| input.next(&mut out), false) {
+            Ok(Step::Continue) => {}
+            Ok(Step::Output(x)) => {
+                let _ = if opts.ascii_out && (0..128).contains(&x) {
+                    write!(out, "{}", x as u8 as char)
+                } else {
+                    writeln!(out, "{}", x)
+                };
+            }
+            Err(Error::Halted) => break EXIT_HALT,
+            Err(Error::NoInput) if input.error.is_some() => {
+                eprintln!("intcode: {}", input.error.take().unwrap());
+                break EXIT_USAGE;
+            }
+            Err(Error::NoInput) => {
+                eprintln!("intcode: out of input at ip {}", ip);
+                break EXIT_STARVED;
+            }
+            Err(e) => {
+                eprintln!("intcode: fault at ip {}: {:?}", ip, e);
+                break EXIT_FAULT;
+            }
+        }
+    };
+
+    let _ = out.flush();
+    if opts.profile {
+        profile.report();
+    }
+    if let (Some(path), Some(initial)) = (&opts.dump, &initial) {
+        let dump = Dump::new(&vm).diff(initial);
+        let text = if path.ends_with(".html") {
+            dump.to_html()
+        } else {
+            dump.to_string()
+        };
+        if let Err(e) = std::fs::write(path, text) {
+            eprintln!("intcode: {}: {}", path, e);
+            exit(EXIT_USAGE)
+        }
+    }
+    let trace = vm.take_trace();
+    if let (true, Some(trace)) = (opts.taint, &trace) {
+        eprint!("{}", intcode::taint(trace, &opts.taint_cells));
+    }
+    if let (Some(path), Some(trace)) = (&opts.record, trace) {
+        let written = std::fs::File::create(path)
+            .map(std::io::BufWriter::new)
+            .and_then(|w| trace.write_to(w));
+        if let Err(e) = written {
+            eprintln!("intcode: {}: {}", path, e);
+            exit(EXIT_USAGE)
+        }
+    }
+    exit(status)
+}
+
+fn replay(vm: Vm, path: &str) -> i32 {
+    let trace = std::fs::File::open(path)
+        .map(std::io::BufReader::new)
+        .and_then(|r| Trace::read_from(r, vm.registry()))
+        .unwrap_or_else(|e| {
+            eprintln!("intcode: {}: {}", path, e);
+            exit(EXIT_USAGE)
+        });
+    match intcode::replay(vm, &trace) {
+        None => {
+            println!(
+                "{} instructions replayed, no divergence",
+                trace.entries.len()
+            );
+            EXIT_HALT
+        }
+        Some(divergence) => {
+            println!("{}", divergence);
+            EXIT_DIVERGED
+        }
+    }
+}
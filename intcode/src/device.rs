@@ -0,0 +1,293 @@
+//! Memory-mapped devices and interrupts.
+//!
+//! A [`Device`] attached to a range of addresses with [`Vm::attach`] receives
+//! every read and write the program makes to that range, in place of the
+//! machine's own memory. Devices are also ticked once per executed
+//! instruction, and may raise an interrupt from there.
+//!
+//! An interrupt pushes `ip` onto a stack kept at the relative base (the cell
+//! at `base` receives `ip`, then `base` is incremented) and jumps to the
+//! interrupt vector. A handler returns to the interrupted code with
+//!
+//! ```text
+//! 109,-1      offset #-1
+//! 2106,0,0    jz #0, rel(0)
+//! ```
+use super::{Error, Mode, Vm};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+pub trait Device: Send {
+    /// Read the cell at `offset` from the start of the mapped range
+    fn read(&mut self, offset: usize) -> isize;
+
+    /// Write the cell at `offset` from the start of the mapped range
+    fn write(&mut self, offset: usize, value: isize);
+
+    /// Called before each instruction is executed. An input instruction
+    /// that is retried after [`Error::NoInput`] is only ticked the first
+    /// time. Returning an address raises an interrupt with that vector
+    fn tick(&mut self) -> Option<usize> {
+        None
+    }
+}
+
+#[derive(Clone)]
+struct Mapping {
+    range: Range<usize>,
+    device: Arc<Mutex<dyn Device>>,
+}
+
+/// Devices attached to a machine. Clones of a machine share its devices
+#[derive(Clone, Default)]
+pub(crate) struct Devices(Vec<Mapping>);
+
+impl std::fmt::Debug for Devices {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|m| &m.range))
+            .finish()
+    }
+}
+
+/// Two sets of devices are equal if they map the same ranges to the very same
+/// device instances
+impl PartialEq for Devices {
+    fn eq(&self, other: &Devices) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(other.0.iter())
+                .all(|(a, b)| a.range == b.range && Arc::ptr_eq(&a.device, &b.device))
+    }
+}
+
+impl Devices {
+    fn find(&self, addr: usize) -> Option<&Mapping> {
+        self.0.iter().find(|m| m.range.contains(&addr))
+    }
+
+    pub(crate) fn read(&self, addr: usize) -> Option<isize> {
+        let m = self.find(addr)?;
+        Some(m.device.lock().unwrap().read(addr - m.range.start))
+    }
+
+    /// Returns false if no device is mapped at `addr`
+    pub(crate) fn write(&self, addr: usize, value: isize) -> bool {
+        match self.find(addr) {
+            Some(m) => {
+                m.device.lock().unwrap().write(addr - m.range.start, value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Tick every device, returning the first interrupt vector raised
+    pub(crate) fn tick(&self) -> Option<usize> {
+        let mut vector = None;
+        for m in &self.0 {
+            let v = m.device.lock().unwrap().tick();
+            vector = vector.or(v);
+        }
+        vector
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Vm {
+    /// Map `device` over the addresses in `range`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` overlaps a range that already has a device attached
+    pub fn attach<D: Device + 'static>(&mut self, range: Range<usize>, device: Arc<Mutex<D>>) {
+        assert!(
+            self.devices
+                .0
+                .iter()
+                .all(|m| m.range.end <= range.start || range.end <= m.range.start),
+            "device range {:?} overlaps an existing device",
+            range
+        );
+        self.devices.0.push(Mapping { range, device });
+    }
+
+    /// Detach every device, returning their addresses to ordinary memory
+    pub fn detach_all(&mut self) {
+        self.devices.0.clear();
+    }
+
+    /// Push `ip` onto the stack at the relative base and jump to `vector`
+    pub fn interrupt(&mut self, vector: usize) -> Result<(), Error> {
+        self.store_or_extend(Mode::Relative(0), self.ip as isize)?;
+        self.base += 1;
+        self.ip = vector;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Raises an interrupt every `period` instructions and counts how many
+    /// were raised, readable at offset 0
+    struct Timer {
+        period: usize,
+        elapsed: usize,
+        fired: isize,
+        vector: usize,
+    }
+
+    impl Device for Timer {
+        fn read(&mut self, _: usize) -> isize {
+            self.fired
+        }
+
+        fn write(&mut self, _: usize, value: isize) {
+            self.period = value as usize;
+        }
+
+        fn tick(&mut self) -> Option<usize> {
+            self.elapsed += 1;
+            if self.elapsed.is_multiple_of(self.period) {
+                self.fired += 1;
+                Some(self.vector)
+            } else {
+                None
+            }
+        }
+    }
+
+    /// A tiny display, like the arcade screen of day 13
+    struct Framebuffer {
+        cols: usize,
+        cells: Vec<isize>,
+    }
+
+    impl Device for Framebuffer {
+        fn read(&mut self, offset: usize) -> isize {
+            self.cells[offset]
+        }
+
+        fn write(&mut self, offset: usize, value: isize) {
+            self.cells[offset] = value;
+        }
+    }
+
+    impl Framebuffer {
+        fn render(&self) -> String {
+            self.cells
+                .chunks(self.cols)
+                .map(|row| {
+                    row.iter()
+                        .map(|&c| if c == 0 { '.' } else { '#' })
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    }
+
+    #[test]
+    fn framebuffer() {
+        let fb = Arc::new(Mutex::new(Framebuffer {
+            cols: 3,
+            cells: vec![0; 6],
+        }));
+        // Draw a diagonal, then read a pixel back and output it
+        let mut vm = "1101,0,1,100,1101,0,1,104,4,104,99".parse::<Vm>().unwrap();
+        vm.attach(100..106, fb.clone());
+        assert_eq!(vm.run(std::iter::empty(), false), Ok(1));
+        assert_eq!(fb.lock().unwrap().render(), "#..\n.#.");
+        // Memory behind the device is untouched
+        assert!(vm.data.len() <= 100);
+    }
+
+    #[test]
+    fn timer_interrupt() {
+        let timer = Arc::new(Mutex::new(Timer {
+            period: 1000,
+            elapsed: 0,
+            fired: 0,
+            vector: 20,
+        }));
+        // Main loop at 0 spins on jz forever, after setting base to 50 and
+        // the timer period to 4. The handler at 20 outputs the number of
+        // interrupts seen and returns
+        let mut program = vec![0; 30];
+        program[..9].copy_from_slice(&[109, 50, 1101, 0, 4, 200, 1106, 0, 6]);
+        program[20..27].copy_from_slice(&[4, 200, 109, -1, 2106, 0, 0]);
+        let mut vm = Vm::new(program);
+        vm.attach(200..201, timer.clone());
+
+        assert_eq!(vm.run(std::iter::empty(), false), Ok(1));
+        assert_eq!(vm.base, 51);
+        // The interrupted instruction's address was pushed on the stack
+        assert_eq!(vm.data[50], 6);
+        assert_eq!(vm.run(std::iter::empty(), false), Ok(2));
+        assert_eq!(vm.base, 51);
+        assert_eq!(timer.lock().unwrap().fired, 2);
+    }
+
+    #[test]
+    fn starved_timer() {
+        let timer = Arc::new(Mutex::new(Timer {
+            period: 3,
+            elapsed: 0,
+            fired: 0,
+            vector: 0,
+        }));
+        let mut vm = "3,0,99".parse::<Vm>().unwrap();
+        vm.attach(100..101, timer.clone());
+        // Polling for input only ticks the input instruction once
+        for _ in 0..5 {
+            assert_eq!(vm.step(|| None, false), Err(Error::NoInput));
+        }
+        assert_eq!(timer.lock().unwrap().elapsed, 1);
+        assert_eq!(vm.step(|| Some(5), false), Ok(crate::Step::Continue));
+        assert_eq!(timer.lock().unwrap().elapsed, 1);
+        // The halt ticks, but a halted machine doesn't, so the third tick
+        // never raises an interrupt that would revive it
+        for _ in 0..3 {
+            assert_eq!(vm.step(|| None, false), Err(Error::Halted));
+        }
+        let timer = timer.lock().unwrap();
+        assert_eq!((timer.elapsed, timer.fired), (2, 0));
+    }
+
+    #[test]
+    fn host_interrupt() {
+        let mut vm = "109,20,1105,1,2,104,7,109,-1,2106,0,0"
+            .parse::<Vm>()
+            .unwrap();
+        for _ in 0..3 {
+            vm.step(|| None, false).unwrap();
+        }
+        assert_eq!(vm.ip, 2);
+        vm.interrupt(5).unwrap();
+        assert_eq!(vm.run(std::iter::empty(), false), Ok(7));
+        vm.step(|| None, false).unwrap();
+        vm.step(|| None, false).unwrap();
+        assert_eq!((vm.ip, vm.base), (2, 20));
+    }
+
+    #[test]
+    #[should_panic]
+    fn overlapping_devices() {
+        let fb = || {
+            Arc::new(Mutex::new(Framebuffer {
+                cols: 1,
+                cells: vec![0; 4],
+            }))
+        };
+        let mut vm = Vm::new(vec![99]);
+        vm.attach(10..14, fb());
+        vm.attach(13..17, fb());
+    }
+}
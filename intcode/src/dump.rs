@@ -0,0 +1,361 @@
+//! Memory dumps for looking at a machine's state.
+//!
+//! A [`Dump`] lays memory out like a hex dump, a fixed number of cells to a
+//! row, except that code is shown one instruction to a row along with its
+//! disassembly. Code is found by following the control flow from address 0
+//! and from `ip`, so data that happens to decode as an instruction is still
+//! shown as data. The cells `ip` and `base` point at are marked with `>` and
+//! `@`, and given an earlier snapshot of memory, cells that changed since
+//! are marked with `*` and listed at the end. Long runs of zeros, such as
+//! memory the machine grew into, are collapsed to a single line.
+//!
+//! ```
+//! use intcode::{Dump, Vm};
+//!
+//! let mut vm = "1101,2,3,7,4,7,99,0".parse::<Vm>().unwrap();
+//! let before = vm.data.clone();
+//! vm.run(std::iter::empty(), false).unwrap();
+//! let dump = Dump::new(&vm).diff(&before).to_string();
+//! assert!(dump.contains("1101      2      3      7  add #2, #3, 7"));
+//! assert!(dump.contains("   7      5*"));
+//! ```
+use super::optimize::Cfg;
+use super::{Opcode, Vm};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Rows of data cells that are all zero before they are collapsed
+const ZERO_ROWS: usize = 2;
+
+/// A rendering of a machine's memory
+#[derive(Clone, Debug)]
+pub struct Dump<'a> {
+    vm: &'a Vm,
+    before: Option<&'a [isize]>,
+    columns: usize,
+    code: BTreeMap<usize, Opcode>,
+}
+
+/// A row of the dump: a run of cells, which is either one instruction or
+/// up to a row's worth of data
+struct Row {
+    addr: usize,
+    len: usize,
+    op: Option<Opcode>,
+}
+
+impl<'a> Dump<'a> {
+    pub fn new(vm: &'a Vm) -> Dump<'a> {
+        Dump {
+            vm,
+            before: None,
+            columns: 8,
+            code: find_code(vm),
+        }
+    }
+
+    /// Compare against memory as it was at some earlier point
+    pub fn diff(mut self, before: &'a [isize]) -> Dump<'a> {
+        self.before = Some(before);
+        self
+    }
+
+    /// Number of data cells per row, 8 by default
+    pub fn columns(mut self, columns: usize) -> Dump<'a> {
+        self.columns = columns.max(1);
+        self
+    }
+
+    /// Cells that differ from the earlier snapshot, as address, old value
+    /// and new value. Cells past the end of either are taken to be zero
+    pub fn changes(&self) -> Vec<(usize, isize, isize)> {
+        let before = match self.before {
+            Some(before) => before,
+            None => return Vec::new(),
+        };
+        let cell = |data: &[isize], addr| data.get(addr).copied().unwrap_or(0);
+        (0..before.len().max(self.vm.data.len()))
+            .map(|addr| (addr, cell(before, addr), cell(&self.vm.data, addr)))
+            .filter(|(_, old, new)| old != new)
+            .collect()
+    }
+
+    fn changed(&self, addr: usize) -> bool {
+        self.before
+            .map(|before| before.get(addr).copied().unwrap_or(0) != self.value(addr))
+            .unwrap_or(false)
+    }
+
+    fn value(&self, addr: usize) -> isize {
+        self.vm.data.get(addr).copied().unwrap_or(0)
+    }
+
+    /// Whether a row is worth showing in full
+    fn interesting(&self, row: &Row) -> bool {
+        row.op.is_some()
+            || (row.addr..row.addr + row.len).any(|addr| {
+                self.value(addr) != 0
+                    || self.changed(addr)
+                    || addr == self.vm.ip
+                    || addr == self.vm.base
+            })
+    }
+
+    fn rows(&self) -> Vec<Row> {
+        let len = self.vm.data.len().max(self.before.map_or(0, |b| b.len()));
+        let mut rows = Vec::new();
+        let mut addr = 0;
+        while addr < len {
+            if let Some(&op) = self.code.get(&addr) {
+                rows.push(Row {
+                    addr,
+                    len: op.size(),
+                    op: Some(op),
+                });
+                addr += op.size();
+                continue;
+            }
+            // Data rows end at a multiple of the row size, or where code starts
+            let end = (addr / self.columns + 1) * self.columns;
+            let end = self.code.range(addr..end).next().map_or(end, |(&a, _)| a);
+            let end = end.min(len);
+            rows.push(Row {
+                addr,
+                len: end - addr,
+                op: None,
+            });
+            addr = end;
+        }
+        rows
+    }
+
+    /// Render every row, with `cell` formatting each cell, `asm` the
+    /// disassembly of an instruction and `skip` a run of zeros
+    fn render(
+        &self,
+        out: &mut String,
+        cell: impl Fn(&mut String, usize, &str),
+        asm: impl Fn(&mut String, &Opcode),
+        skip: impl Fn(&mut String, usize, usize),
+    ) {
+        let width = self
+            .vm
+            .data
+            .iter()
+            .chain(self.before.unwrap_or(&[]))
+            .map(|x| x.to_string().len())
+            .max()
+            .unwrap_or(1)
+            .max(4);
+        let addr_width = self.vm.data.len().max(1).to_string().len().max(4);
+
+        let rows = self.rows();
+        let mut n = 0;
+        while n < rows.len() {
+            let zeros = rows[n..]
+                .iter()
+                .take_while(|row| !self.interesting(row))
+                .count();
+            if zeros >= ZERO_ROWS {
+                let skipped = rows[n..n + zeros].iter().map(|row| row.len).sum();
+                skip(out, rows[n].addr, skipped);
+                n += zeros;
+                continue;
+            }
+
+            let row = &rows[n];
+            let _ = write!(out, "{:>w$} ", row.addr, w = addr_width);
+            for addr in row.addr..row.addr + row.len {
+                let marker = if addr == self.vm.ip {
+                    '>'
+                } else if addr == self.vm.base {
+                    '@'
+                } else {
+                    ' '
+                };
+                let changed = if self.changed(addr) { '*' } else { ' ' };
+                let text = format!(" {}{:>w$}{}", marker, self.value(addr), changed, w = width);
+                cell(out, addr, &text);
+            }
+            if let Some(op) = &row.op {
+                // Line the disassembly up past the longest built-in instruction
+                let pad = 4usize.saturating_sub(row.len) * (width + 3) + 1;
+                out.push_str(&" ".repeat(pad));
+                asm(out, op);
+            }
+            while out.ends_with(' ') {
+                out.pop();
+            }
+            out.push('\n');
+            n += 1;
+        }
+    }
+
+    /// A standalone HTML page, with changed cells highlighted and their
+    /// previous values shown on hover
+    pub fn to_html(&self) -> String {
+        let mut out = String::from(concat!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+            "<title>intcode memory</title>\n<style>\n",
+            ".ip { background: #9cf; }\n",
+            ".base { background: #fd9; }\n",
+            ".changed { background: #f99; }\n",
+            ".asm { color: #070; }\n",
+            ".skip { color: #888; }\n",
+            "</style>\n</head>\n<body>\n<pre>\n",
+        ));
+        let _ = writeln!(out, "{}", escape(&self.header()));
+        let before = self.before;
+        self.render(
+            &mut out,
+            |out, addr, text| {
+                let mut classes = Vec::new();
+                if addr == self.vm.ip {
+                    classes.push("ip");
+                }
+                if addr == self.vm.base {
+                    classes.push("base");
+                }
+                if self.changed(addr) {
+                    classes.push("changed");
+                }
+                if classes.is_empty() {
+                    out.push_str(text);
+                    return;
+                }
+                let _ = write!(out, "<span class=\"{}\"", classes.join(" "));
+                if let Some(before) = before.filter(|_| self.changed(addr)) {
+                    let old = before.get(addr).copied().unwrap_or(0);
+                    let _ = write!(out, " title=\"was {}\"", old);
+                }
+                let _ = write!(out, ">{}</span>", escape(text));
+            },
+            |out, op| {
+                let _ = write!(
+                    out,
+                    "<span class=\"asm\">{}</span>",
+                    escape(&op.to_string())
+                );
+            },
+            |out, addr, len| {
+                let _ = writeln!(out, "<span class=\"skip\">{}</span>", skipped(addr, len));
+            },
+        );
+        out.push_str("</pre>\n</body>\n</html>\n");
+        out
+    }
+
+    fn header(&self) -> String {
+        let mut header = format!("ip {} (>), base {} (@)", self.vm.ip, self.vm.base);
+        if self.before.is_some() {
+            header.push_str(&format!(", {} changed (*)", self.changes().len()));
+        }
+        header
+    }
+}
+
+fn skipped(addr: usize, len: usize) -> String {
+    format!("  {}..{}: {} zeros", addr, addr + len, len)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Text rendering, followed by a list of the changes
+impl std::fmt::Display for Dump<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut out = String::new();
+        self.render(
+            &mut out,
+            |out, _, text| out.push_str(text),
+            |out, op| {
+                let _ = write!(out, "{}", op);
+            },
+            |out, addr, len| {
+                let _ = writeln!(out, "{}", skipped(addr, len));
+            },
+        );
+        writeln!(f, "{}", self.header())?;
+        f.write_str(&out)?;
+        for (addr, old, new) in self.changes() {
+            writeln!(f, "{}: {} -> {}", addr, old, new)?;
+        }
+        Ok(())
+    }
+}
+
+/// Instructions reachable from address 0 and from `ip`, following jumps
+/// with immediate targets
+fn find_code(vm: &Vm) -> BTreeMap<usize, Opcode> {
+    let mut code = Cfg::build(vm, &[0, vm.ip]).instrs;
+    // Drop instructions that overlap ones before them
+    let mut end = 0;
+    code.retain(|&addr, op| {
+        let keep = addr >= end;
+        if keep {
+            end = addr + op.size();
+        }
+        keep
+    });
+    code
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    #[test]
+    fn layout() {
+        let program = assemble(
+            "       jmp start
+             msg:   data 1, 2, 3, 1101, 1, 2, 3, 4, 5
+             start: output msg
+                    halt",
+        )
+        .unwrap();
+        let mut vm = Vm::new(program);
+        vm.data.resize(100, 0);
+        vm.ip = 12;
+        vm.base = 5;
+        // The data that happens to decode as an add stays data
+        let expected = "\
+ip 12 (>), base 5 (@)
+   0   1105      1     12         jnz #1, #12
+   3      1      2  @   3   1101      1
+   8      2      3      4      5
+  12  >   4      3                output 3
+  14     99                       halt
+  15..100: 85 zeros
+";
+        assert_eq!(Dump::new(&vm).to_string(), expected);
+    }
+
+    #[test]
+    fn changes() {
+        let mut vm = "3,9,1002,9,2,10,4,10,99,0,0".parse::<Vm>().unwrap();
+        let before = vm.data.clone();
+        vm.run(vec![21].into_iter(), false).unwrap();
+        let dump = Dump::new(&vm).diff(&before);
+        assert_eq!(dump.changes(), vec![(9, 0, 21), (10, 0, 42)]);
+
+        let text = dump.to_string();
+        assert!(text.starts_with("ip 8 (>), base 0 (@), 2 changed (*)\n"));
+        assert!(text.ends_with("9: 0 -> 21\n10: 0 -> 42\n"), "{}", text);
+
+        let html = dump.to_html();
+        assert!(html.contains("<span class=\"changed\" title=\"was 0\">    42*</span>"));
+        assert!(html.contains("<span class=\"asm\">mul 9, #2, 10</span>"));
+    }
+
+    #[test]
+    fn negative_jump() {
+        // Jumping to -1 halts, so the halt after it is never reached
+        let vm = "1105,1,-1,99".parse::<Vm>().unwrap();
+        let code = find_code(&vm);
+        assert_eq!(code.keys().copied().collect::<Vec<_>>(), vec![0]);
+    }
+}
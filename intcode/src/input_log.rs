@@ -0,0 +1,140 @@
+//! Recording and playback of the inputs fed to a machine.
+//!
+//! Interactive programs like the day 13 arcade cabinet are driven by inputs
+//! that are computed from their outputs as they run. Wrapping the input source
+//! with [`InputLog::record`] keeps every value the machine actually consumed,
+//! so the session can be saved and later played back exactly with
+//! [`InputLog::play`], without re-deriving the inputs.
+//!
+//! Logs are saved in the same comma separated format as programs, so a saved
+//! log can also be passed to `intcode --input-file`.
+//!
+//! ```
+//! use intcode::{InputLog, Vm};
+//!
+//! let vm = "3,9,4,9,1105,1,0,0,0,0".parse::<Vm>().unwrap();
+//! let mut log = InputLog::new();
+//!
+//! let mut live = vm.clone();
+//! assert_eq!(live.run(log.record(std::iter::repeat(7)), false), Ok(7));
+//! assert_eq!(live.run(log.record(std::iter::repeat(8)), false), Ok(8));
+//! assert_eq!(log.to_string(), "7,8");
+//!
+//! let mut replayed = vm;
+//! let mut player = log.play();
+//! assert_eq!(replayed.run(&mut player, false), Ok(7));
+//! assert_eq!(replayed.run(&mut player, false), Ok(8));
+//! ```
+use super::Error;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Values consumed by a machine, in the order they were consumed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputLog {
+    values: Vec<isize>,
+}
+
+/// Input source that appends every value it yields to an [`InputLog`]
+pub struct Recorder<'a, I> {
+    log: &'a mut InputLog,
+    source: I,
+}
+
+impl<I: Iterator<Item = isize>> Iterator for Recorder<'_, I> {
+    type Item = isize;
+    fn next(&mut self) -> Option<isize> {
+        let x = self.source.next()?;
+        self.log.values.push(x);
+        Some(x)
+    }
+}
+
+/// Input source yielding the values of an [`InputLog`]. Pass it to
+/// [`Vm::run`](super::Vm::run) by reference to continue where the previous
+/// call left off
+pub type Player<'a> = std::iter::Copied<std::slice::Iter<'a, isize>>;
+
+impl InputLog {
+    pub fn new() -> InputLog {
+        InputLog::default()
+    }
+
+    pub fn values(&self) -> &[isize] {
+        &self.values
+    }
+
+    /// Wrap `source`, logging each value that is taken from it. Values are
+    /// only pulled from `source` when the machine executes an input
+    /// instruction, so the log holds exactly the inputs that were consumed
+    pub fn record<I: Iterator<Item = isize>>(&mut self, source: I) -> Recorder<'_, I> {
+        Recorder { log: self, source }
+    }
+
+    /// Replay the logged values from the start
+    pub fn play(&self) -> Player<'_> {
+        self.values.iter().copied()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, format!("{}\n", self))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<InputLog> {
+        std::fs::read_to_string(path)?
+            .parse()
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid input log"))
+    }
+}
+
+impl std::fmt::Display for InputLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, x) in self.values.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { "" } else { "," }, x)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for InputLog {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<isize>().map_err(|_| Error::InvalidData))
+            .collect::<Result<_, _>>()?;
+        Ok(InputLog { values })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Vm;
+
+    #[test]
+    fn only_consumed_values_are_logged() {
+        // Reads one value, outputs it, then halts
+        let mut vm = "3,5,4,5,99,0".parse::<Vm>().unwrap();
+        let mut log = InputLog::new();
+        assert_eq!(vm.run(log.record(1..), false), Ok(1));
+        assert_eq!(vm.run(log.record(1..), false), Err(Error::Halted));
+        assert_eq!(log.values(), &[1]);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut log = InputLog::new();
+        log.record(vec![-1, 0, 1, 0].into_iter()).for_each(drop);
+        let path = std::env::temp_dir().join(format!("intcode-log-{}", std::process::id()));
+        log.save(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "-1,0,1,0\n");
+        assert_eq!(InputLog::load(&path).unwrap(), log);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!("".parse::<InputLog>(), Ok(InputLog::new()));
+        assert_eq!("1,x".parse::<InputLog>(), Err(Error::InvalidData));
+    }
+}
@@ -0,0 +1,962 @@
+//! A compiler for a tiny imperative language, targeting intcode through the
+//! assembler.
+//!
+//! ```text
+//! // Comments run to the end of the line
+//! fn fact(n) {
+//!     if n < 2 {
+//!         return 1;
+//!     }
+//!     return n * fact(n - 1);
+//! }
+//!
+//! let n = input();
+//! while n >= 0 {
+//!     output(fact(n));
+//!     n = input();
+//! }
+//! ```
+//!
+//! Values are integers, with `0` as false and anything else as true. Top
+//! level statements run in order and may declare global variables; functions
+//! may be declared anywhere and see every global. Variables declared inside
+//! a function are local to the whole function, whichever block they appear
+//! in. Operators, from loosest to tightest binding:
+//!
+//! - `||` and `&&`, which short-circuit and yield `0` or `1`
+//! - `==`, `!=`, `<`, `<=`, `>` and `>=`
+//! - `+` and `-`
+//! - `*`, `/` and `%`. Division is only defined for a non-negative dividend
+//!   and a positive divisor, and is done by the standard library
+//! - unary `-` and `!`
+//!
+//! `input()` reads a value, and the `output(x);` statement writes one.
+//!
+//! Function calls use the stack frame convention of the assembler, so
+//! recursion works up to the memory limit of the machine. Temporary values
+//! live on the same stack.
+use super::asm::assemble;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileError {
+    /// Line of the source the error was found on, starting from 1
+    pub line: usize,
+    pub msg: String,
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+fn error<T>(line: usize, msg: String) -> Result<T, CompileError> {
+    Err(CompileError { line, msg })
+}
+
+/// Compile a program into an intcode image
+pub fn compile(source: &str) -> Result<Vec<isize>, CompileError> {
+    let asm = compile_to_asm(source)?;
+    assemble(&asm).map_err(|e| CompileError {
+        line: 0,
+        msg: format!("generated invalid assembly: {}", e),
+    })
+}
+
+/// Compile a program into assembly source for [`assemble`](super::assemble)
+pub fn compile_to_asm(source: &str) -> Result<String, CompileError> {
+    let tokens = lex(source)?;
+    let program = Parser { tokens, pos: 0 }.program()?;
+    Codegen::new(&program)?.program(&program)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(isize),
+    Ident(String),
+    Punct(&'static str),
+    Eof,
+}
+
+const PUNCTUATION: [&str; 21] = [
+    "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "!", "=", "(", ")", "{",
+    "}", ",", ";",
+];
+
+fn lex(source: &str) -> Result<Vec<(usize, Token)>, CompileError> {
+    let mut tokens = Vec::new();
+    for (n, line) in source.lines().enumerate() {
+        let line_no = n + 1;
+        let line = line.split("//").next().unwrap_or_default();
+        let mut rest = line.trim_start();
+        while !rest.is_empty() {
+            let c = rest.chars().next().unwrap_or_default();
+            let len = if c.is_ascii_digit() {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                match rest[..len].parse() {
+                    Ok(x) => tokens.push((line_no, Token::Num(x))),
+                    Err(_) => {
+                        return error(line_no, format!("number {} is too large", &rest[..len]))
+                    }
+                }
+                len
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                tokens.push((line_no, Token::Ident(rest[..len].to_string())));
+                len
+            } else if let Some(p) = PUNCTUATION.iter().find(|p| rest.starts_with(*p)) {
+                tokens.push((line_no, Token::Punct(p)));
+                p.len()
+            } else {
+                return error(line_no, format!("unexpected character '{}'", c));
+            };
+            rest = rest[len..].trim_start();
+        }
+    }
+    let last = source.lines().count().max(1);
+    tokens.push((last, Token::Eof));
+    Ok(tokens)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Num(isize),
+    Var(usize, String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Call(usize, String, Vec<Expr>),
+    Input,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Stmt {
+    Let(usize, String, Expr),
+    Assign(usize, String, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Output(Expr),
+    Expr(Expr),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Function {
+    line: usize,
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+#[derive(Default)]
+struct Program {
+    functions: Vec<Function>,
+    main: Vec<Stmt>,
+}
+
+const KEYWORDS: [&str; 8] = [
+    "fn", "let", "if", "else", "while", "return", "input", "output",
+];
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].1
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].0
+    }
+
+    fn next(&mut self) -> Token {
+        let tok = self.tokens[self.pos].1.clone();
+        if tok != Token::Eof {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn describe(tok: &Token) -> String {
+        match tok {
+            Token::Num(x) => x.to_string(),
+            Token::Ident(s) => s.clone(),
+            Token::Punct(p) => p.to_string(),
+            Token::Eof => "end of input".to_string(),
+        }
+    }
+
+    fn eat(&mut self, p: &str) -> bool {
+        match self.peek() {
+            Token::Punct(q) if *q == p => {
+                self.next();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn keyword(&mut self, kw: &str) -> bool {
+        match self.peek() {
+            Token::Ident(s) if s == kw => {
+                self.next();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, p: &str) -> Result<(), CompileError> {
+        if self.eat(p) {
+            Ok(())
+        } else {
+            let found = Parser::describe(self.peek());
+            error(self.line(), format!("expected '{}', found '{}'", p, found))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, CompileError> {
+        let line = self.line();
+        match self.next() {
+            Token::Ident(s) if !KEYWORDS.contains(&s.as_str()) => Ok(s),
+            tok => error(
+                line,
+                format!("expected a name, found '{}'", Parser::describe(&tok)),
+            ),
+        }
+    }
+
+    fn program(mut self) -> Result<Program, CompileError> {
+        let mut program = Program::default();
+        while *self.peek() != Token::Eof {
+            if self.keyword("fn") {
+                program.functions.push(self.function()?);
+            } else {
+                program.main.push(self.statement()?);
+            }
+        }
+        Ok(program)
+    }
+
+    fn function(&mut self) -> Result<Function, CompileError> {
+        let line = self.line();
+        let name = self.ident()?;
+        self.expect("(")?;
+        let mut params = Vec::new();
+        if !self.eat(")") {
+            loop {
+                params.push(self.ident()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        let body = self.block()?;
+        Ok(Function {
+            line,
+            name,
+            params,
+            body,
+        })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            if *self.peek() == Token::Eof {
+                return error(self.line(), "expected '}', found end of input".to_string());
+            }
+            stmts.push(self.statement()?);
+        }
+        Ok(stmts)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, CompileError> {
+        let line = self.line();
+        let stmt = if self.keyword("let") {
+            let name = self.ident()?;
+            self.expect("=")?;
+            Stmt::Let(line, name, self.expr()?)
+        } else if self.keyword("if") {
+            return self.if_statement();
+        } else if self.keyword("while") {
+            let cond = self.expr()?;
+            return Ok(Stmt::While(cond, self.block()?));
+        } else if self.keyword("return") {
+            if self.eat(";") {
+                return Ok(Stmt::Return(None));
+            }
+            Stmt::Return(Some(self.expr()?))
+        } else if self.keyword("output") {
+            self.expect("(")?;
+            let value = self.expr()?;
+            self.expect(")")?;
+            Stmt::Output(value)
+        } else if self.keyword("fn") {
+            return error(
+                line,
+                "functions can only be declared at the top level".into(),
+            );
+        } else {
+            let is_assign = matches!(self.peek(), Token::Ident(_))
+                && self.tokens[self.pos + 1].1 == Token::Punct("=");
+            if is_assign {
+                let name = self.ident()?;
+                self.expect("=")?;
+                Stmt::Assign(line, name, self.expr()?)
+            } else {
+                Stmt::Expr(self.expr()?)
+            }
+        };
+        self.expect(";")?;
+        Ok(stmt)
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, CompileError> {
+        let cond = self.expr()?;
+        let then = self.block()?;
+        let otherwise = if !self.keyword("else") {
+            Vec::new()
+        } else if self.keyword("if") {
+            vec![self.if_statement()?]
+        } else {
+            self.block()?
+        };
+        Ok(Stmt::If(cond, then, otherwise))
+    }
+
+    /// Parse a left associative chain of binary operators from `ops`, with
+    /// operands parsed by `operand`
+    fn chain(
+        &mut self,
+        ops: &[(&str, BinOp)],
+        operand: fn(&mut Parser) -> Result<Expr, CompileError>,
+    ) -> Result<Expr, CompileError> {
+        let mut lhs = operand(self)?;
+        'outer: loop {
+            for &(p, op) in ops {
+                if self.eat(p) {
+                    let rhs = operand(self)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        self.chain(&[("||", BinOp::Or)], |p| {
+            p.chain(&[("&&", BinOp::And)], Parser::comparison)
+        })
+    }
+
+    fn comparison(&mut self) -> Result<Expr, CompileError> {
+        use BinOp::*;
+        let ops = [
+            ("==", Eq),
+            ("!=", Ne),
+            ("<=", Le),
+            (">=", Ge),
+            ("<", Lt),
+            (">", Gt),
+        ];
+        self.chain(&ops, |p| {
+            p.chain(&[("+", Add), ("-", Sub)], |p| {
+                p.chain(&[("*", Mul), ("/", Div), ("%", Mod)], Parser::unary)
+            })
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        if self.eat("-") {
+            return Ok(match self.unary()? {
+                Expr::Num(x) => Expr::Num(-x),
+                e => Expr::Neg(Box::new(e)),
+            });
+        }
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        let line = self.line();
+        match self.next() {
+            Token::Num(x) => Ok(Expr::Num(x)),
+            Token::Punct("(") => {
+                let e = self.expr()?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            Token::Ident(s) if s == "input" => {
+                self.expect("(")?;
+                self.expect(")")?;
+                Ok(Expr::Input)
+            }
+            Token::Ident(s) if s == "output" => {
+                error(line, "output() is a statement, not a value".into())
+            }
+            Token::Ident(s) if !KEYWORDS.contains(&s.as_str()) => {
+                if !self.eat("(") {
+                    return Ok(Expr::Var(line, s));
+                }
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Expr::Call(line, s, args))
+            }
+            tok => error(
+                line,
+                format!("expected an expression, found '{}'", Parser::describe(&tok)),
+            ),
+        }
+    }
+}
+
+/// Collect the names declared with `let` anywhere in `stmts`
+fn declared(stmts: &[Stmt], out: &mut Vec<String>) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Let(_, name, _) if !out.contains(name) => out.push(name.clone()),
+            Stmt::If(_, a, b) => {
+                declared(a, out);
+                declared(b, out);
+            }
+            Stmt::While(_, body) => declared(body, out),
+            _ => {}
+        }
+    }
+}
+
+/// Code generation state. `depth` is the distance between the relative base
+/// and the start of the current frame, so that a variable at `offset` from
+/// the frame is found at `rel(offset - depth)`
+struct Codegen {
+    out: String,
+    labels: usize,
+    globals: HashSet<String>,
+    /// Arity of every function
+    functions: HashMap<String, usize>,
+    /// Frame offsets of the parameters and locals of the current function
+    locals: HashMap<String, isize>,
+    depth: isize,
+    in_function: bool,
+    uses_std: bool,
+}
+
+impl Codegen {
+    fn new(program: &Program) -> Result<Codegen, CompileError> {
+        let mut functions = HashMap::new();
+        for f in &program.functions {
+            if functions.insert(f.name.clone(), f.params.len()).is_some() {
+                return error(f.line, format!("function {} is already defined", f.name));
+            }
+        }
+        let mut globals = Vec::new();
+        declared(&program.main, &mut globals);
+        Ok(Codegen {
+            out: String::new(),
+            labels: 0,
+            globals: globals.into_iter().collect(),
+            functions,
+            locals: HashMap::new(),
+            depth: 0,
+            in_function: false,
+            uses_std: false,
+        })
+    }
+
+    fn emit(&mut self, line: String) {
+        self.out.push_str("    ");
+        self.out.push_str(&line);
+        self.out.push('\n');
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    fn place(&mut self, label: &str) {
+        let _ = writeln!(self.out, "{}:", label);
+    }
+
+    /// Move the relative base, keeping track of the frame depth
+    fn offset(&mut self, n: isize) {
+        if n != 0 {
+            self.emit(format!("offset #{}", n));
+            self.depth += n;
+        }
+    }
+
+    fn var(&self, line: usize, name: &str) -> Result<String, CompileError> {
+        if let Some(offset) = self.locals.get(name) {
+            return Ok(format!("rel({})", offset - self.depth));
+        }
+        if self.globals.contains(name) {
+            return Ok(format!("g_{}", name));
+        }
+        error(line, format!("undefined variable {}", name))
+    }
+
+    /// Operand for an expression that can be used without evaluating it first
+    fn leaf(&self, e: &Expr) -> Result<Option<String>, CompileError> {
+        match e {
+            Expr::Num(x) => Ok(Some(format!("#{}", x))),
+            Expr::Var(line, name) => self.var(*line, name).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Evaluate `e`, pushing its value onto the stack
+    fn expr(&mut self, e: &Expr) -> Result<(), CompileError> {
+        match e {
+            Expr::Num(_) | Expr::Var(..) => {
+                let op = self.leaf(e)?.unwrap_or_default();
+                self.emit(format!("add {}, #0, rel(0)", op));
+                self.offset(1);
+            }
+            Expr::Input => {
+                self.emit("input rel(0)".into());
+                self.offset(1);
+            }
+            Expr::Neg(a) => {
+                self.expr(a)?;
+                self.emit("mul rel(-1), #-1, rel(-1)".into());
+            }
+            Expr::Not(a) => {
+                self.expr(a)?;
+                self.emit("eq rel(-1), #0, rel(-1)".into());
+            }
+            Expr::Binary(op @ (BinOp::And | BinOp::Or), a, b) => {
+                let end = self.label();
+                self.expr(a)?;
+                let jump = if *op == BinOp::And { "jz" } else { "jnz" };
+                self.emit(format!("{} rel(-1), #{}", jump, end));
+                self.offset(-1);
+                self.expr(b)?;
+                self.place(&end);
+                self.emit("eq rel(-1), #0, rel(-1)".into());
+                self.emit("eq rel(-1), #0, rel(-1)".into());
+            }
+            Expr::Binary(op @ (BinOp::Div | BinOp::Mod), a, b) => {
+                self.uses_std = true;
+                self.expr(a)?;
+                self.expr(b)?;
+                let f = if *op == BinOp::Div {
+                    "std_div"
+                } else {
+                    "std_mod"
+                };
+                self.emit(format!("call {}", f));
+                self.emit("add rel(1), #0, rel(-2)".into());
+                self.offset(-1);
+            }
+            Expr::Binary(op, a, b) => {
+                // Numbers and variables are used in place, anything else is
+                // evaluated onto the stack first. The result replaces the
+                // first operand that was pushed, or goes on top of the stack
+                let (lhs, rhs) = (self.leaf(a)?.is_none(), self.leaf(b)?.is_none());
+                if lhs {
+                    self.expr(a)?;
+                }
+                if rhs {
+                    self.expr(b)?;
+                }
+                let pushed = lhs as isize + rhs as isize;
+                let t = format!("rel({})", -pushed);
+                let x = self.leaf(a)?.unwrap_or_else(|| t.clone());
+                let y = self.leaf(b)?.unwrap_or_else(|| "rel(-1)".into());
+                self.binary(*op, &x, &y, &t);
+                self.offset(1 - pushed);
+            }
+            Expr::Call(line, name, args) => {
+                match self.functions.get(name) {
+                    Some(&n) if n == args.len() => {}
+                    Some(&n) => {
+                        return error(
+                            *line,
+                            format!("{} takes {} argument(s), found {}", name, n, args.len()),
+                        )
+                    }
+                    None => return error(*line, format!("undefined function {}", name)),
+                }
+                for arg in args {
+                    self.expr(arg)?;
+                }
+                self.emit(format!("call fn_{}", name));
+                let n = args.len() as isize;
+                self.emit(format!("add rel(1), #0, rel({})", -n));
+                self.offset(1 - n);
+            }
+        }
+        Ok(())
+    }
+
+    /// Emit `t = x op y`. The cell at `rel(0)` is free to use as scratch
+    /// space, and `t` may be the same cell as either operand
+    fn binary(&mut self, op: BinOp, x: &str, y: &str, t: &str) {
+        use BinOp::*;
+        match op {
+            Add => self.emit(format!("add {}, {}, {}", x, y, t)),
+            Sub => match y.strip_prefix('#') {
+                Some(n) => {
+                    let n = n.parse::<isize>().unwrap_or_default().wrapping_neg();
+                    self.emit(format!("add {}, #{}, {}", x, n, t));
+                }
+                None => {
+                    self.emit(format!("mul {}, #-1, rel(0)", y));
+                    self.emit(format!("add {}, rel(0), {}", x, t));
+                }
+            },
+            Mul => self.emit(format!("mul {}, {}, {}", x, y, t)),
+            Lt => self.emit(format!("lt {}, {}, {}", x, y, t)),
+            Gt => self.emit(format!("lt {}, {}, {}", y, x, t)),
+            Le | Ge => {
+                let (x, y) = if op == Le { (y, x) } else { (x, y) };
+                self.emit(format!("lt {}, {}, {}", x, y, t));
+                self.emit(format!("eq {}, #0, {}", t, t));
+            }
+            Eq => self.emit(format!("eq {}, {}, {}", x, y, t)),
+            Ne => {
+                self.emit(format!("eq {}, {}, {}", x, y, t));
+                self.emit(format!("eq {}, #0, {}", t, t));
+            }
+            Div | Mod | And | Or => unreachable!("{:?} is compiled separately", op),
+        }
+    }
+
+    /// Jump to `target` if `cond` is false
+    fn jump_unless(&mut self, cond: &Expr, target: &str) -> Result<(), CompileError> {
+        match self.leaf(cond)? {
+            Some(op) => self.emit(format!("jz {}, #{}", op, target)),
+            None => {
+                self.expr(cond)?;
+                self.offset(-1);
+                self.emit(format!("jz rel(0), #{}", target));
+            }
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, line: usize, name: &str, value: &Expr) -> Result<(), CompileError> {
+        match self.leaf(value)? {
+            Some(op) => {
+                let dest = self.var(line, name)?;
+                self.emit(format!("add {}, #0, {}", op, dest));
+            }
+            None => {
+                self.expr(value)?;
+                self.offset(-1);
+                let dest = self.var(line, name)?;
+                self.emit(format!("add rel(0), #0, {}", dest));
+            }
+        }
+        Ok(())
+    }
+
+    fn ret(&mut self, value: Option<&Expr>) -> Result<(), CompileError> {
+        if !self.in_function {
+            self.emit("halt".into());
+            return Ok(());
+        }
+        // Unwind the frame, then return the value from wherever it ends up
+        let depth = self.depth;
+        match value {
+            Some(v) if self.leaf(v)?.is_none() => {
+                self.expr(v)?;
+                let top = self.depth - 1;
+                self.offset(-self.depth);
+                self.emit(format!("ret rel({})", top));
+            }
+            Some(v) => {
+                self.offset(-depth);
+                let op = self.leaf(v)?.unwrap_or_default();
+                self.emit(format!("ret {}", op));
+            }
+            None => {
+                self.offset(-depth);
+                self.emit("ret #0".into());
+            }
+        }
+        // Code after the return is unreachable, but is compiled as if the
+        // return wasn't there
+        self.depth = depth;
+        Ok(())
+    }
+
+    fn statements(&mut self, stmts: &[Stmt]) -> Result<(), CompileError> {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Let(line, name, value) | Stmt::Assign(line, name, value) => {
+                    self.assign(*line, name, value)?
+                }
+                Stmt::If(cond, then, otherwise) => {
+                    let (other, end) = (self.label(), self.label());
+                    self.jump_unless(cond, &other)?;
+                    self.statements(then)?;
+                    if !otherwise.is_empty() {
+                        self.emit(format!("jmp {}", end));
+                    }
+                    self.place(&other);
+                    self.statements(otherwise)?;
+                    self.place(&end);
+                }
+                Stmt::While(cond, body) => {
+                    let (top, end) = (self.label(), self.label());
+                    self.place(&top);
+                    self.jump_unless(cond, &end)?;
+                    self.statements(body)?;
+                    self.emit(format!("jmp {}", top));
+                    self.place(&end);
+                }
+                Stmt::Return(value) => self.ret(value.as_ref())?,
+                Stmt::Output(value) => match self.leaf(value)? {
+                    Some(op) => self.emit(format!("output {}", op)),
+                    None => {
+                        self.expr(value)?;
+                        self.offset(-1);
+                        self.emit("output rel(0)".into());
+                    }
+                },
+                Stmt::Expr(e) => {
+                    self.expr(e)?;
+                    self.offset(-1);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, f: &Function) -> Result<(), CompileError> {
+        // On entry the return address is at rel(-1), preceded by the
+        // arguments. Locals sit above it
+        let n = f.params.len() as isize;
+        self.locals.clear();
+        for (i, p) in f.params.iter().enumerate() {
+            if self.locals.insert(p.clone(), i as isize - n - 1).is_some() {
+                return error(f.line, format!("parameter {} is repeated", p));
+            }
+        }
+        let mut locals = Vec::new();
+        declared(&f.body, &mut locals);
+        locals.retain(|l| !self.locals.contains_key(l));
+        for (i, l) in locals.iter().enumerate() {
+            self.locals.insert(l.clone(), i as isize);
+        }
+
+        self.place(&format!("fn_{}", f.name));
+        self.depth = 0;
+        self.in_function = true;
+        self.offset(locals.len() as isize);
+        self.statements(&f.body)?;
+        self.ret(None)
+    }
+
+    fn program(mut self, program: &Program) -> Result<String, CompileError> {
+        self.emit("offset #stack".into());
+        self.statements(&program.main)?;
+        self.emit("halt".into());
+        for f in &program.functions {
+            self.function(f)?;
+        }
+        let mut globals = self.globals.iter().cloned().collect::<Vec<_>>();
+        globals.sort();
+        for g in globals {
+            let _ = writeln!(self.out, "g_{}: data 0", g);
+        }
+        if self.uses_std {
+            self.out.push_str("include std\n");
+        }
+        self.out.push_str("stack:\n");
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Error, Vm};
+
+    fn run(source: &str, input: &[isize]) -> Vec<isize> {
+        let program = compile(source).unwrap_or_else(|e| panic!("{}", e));
+        let mut vm = Vm::new(program);
+        let mut input = input.iter().copied();
+        let mut out = Vec::new();
+        loop {
+            match vm.run(&mut input, false) {
+                Ok(x) => out.push(x),
+                Err(Error::Halted) => return out,
+                Err(e) => panic!("{:?} at ip {}", e, vm.ip),
+            }
+        }
+    }
+
+    const FACT: &str = "
+        fn fact(n) {
+            if n < 2 {
+                return 1;
+            }
+            return n * fact(n - 1);
+        }
+
+        let n = input();
+        while n >= 0 {
+            output(fact(n));
+            n = input();
+        }";
+
+    #[test]
+    fn recursion() {
+        assert_eq!(run(FACT, &[0, 1, 5, 10, -1]), vec![1, 1, 120, 3628800]);
+
+        let fib = "
+            fn fib(n) {
+                if n < 2 { return n; }
+                return fib(n - 1) + fib(n - 2);
+            }
+            output(fib(input()));";
+        assert_eq!(run(fib, &[20]), vec![6765]);
+    }
+
+    #[test]
+    fn operators() {
+        let source = "
+            let a = input();
+            let b = input();
+            output(a + b * 2 - (a - b));
+            output(-a * -b);
+            output(a / b);
+            output(a % b);
+            output(a < b);
+            output(a <= b);
+            output(a > b);
+            output(a >= b);
+            output(a == b);
+            output(a != b);
+            output(!a);
+            output(a && b);
+            output(0 || b);";
+        let results = |a, b| run(source, &[a, b]);
+        assert_eq!(
+            results(17, 5),
+            vec![17 + 10 - 12, 85, 3, 2, 0, 0, 1, 1, 0, 1, 0, 1, 1]
+        );
+        assert_eq!(results(4, 4), vec![12, 16, 1, 0, 0, 1, 0, 1, 1, 0, 0, 1, 1]);
+        assert_eq!(results(0, 3), vec![9, 0, 0, 0, 1, 1, 0, 0, 0, 1, 1, 0, 1]);
+    }
+
+    #[test]
+    fn short_circuit() {
+        // The right hand side, and its input, are skipped
+        let source = "
+            let a = input();
+            output(a && input());
+            output(a || input());";
+        assert_eq!(run(source, &[0, 9]), vec![0, 1]);
+        assert_eq!(run(source, &[2, 9]), vec![1, 1]);
+    }
+
+    #[test]
+    fn locals_and_globals() {
+        let source = "
+            let total = 0;
+            fn add(x) {
+                total = total + x;
+                return total;
+            }
+            fn sum_to(n) {
+                let i = 1;
+                let acc = 0;
+                while i <= n {
+                    acc = acc + i;
+                    if i % 2 == 0 { let even = i; add(even); } else if i == 5 { add(100); }
+                    i = i + 1;
+                }
+                return acc;
+            }
+            output(sum_to(input()));
+            output(total);";
+        assert_eq!(run(source, &[10]), vec![55, 2 + 4 + 6 + 8 + 10 + 100]);
+    }
+
+    #[test]
+    fn every_opcode() {
+        let program = compile(FACT).unwrap();
+        let used = Vm::new(program)
+            .disassemble()
+            .iter()
+            .map(|(_, op)| op.name())
+            .collect::<HashSet<_>>();
+        for name in crate::registry::BUILTIN_NAMES.iter() {
+            assert!(used.contains(name), "{} is never emitted", name);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let err = |source: &str| compile(source).unwrap_err().to_string();
+        assert_eq!(err("output(x);"), "line 1: undefined variable x");
+        assert_eq!(err("let a = 1;\nf(a);"), "line 2: undefined function f");
+        assert_eq!(
+            err("fn f(a) { return a; }\nf();"),
+            "line 2: f takes 1 argument(s), found 0"
+        );
+        assert_eq!(
+            err("let a = 1\nlet b = 2;"),
+            "line 2: expected ';', found 'let'"
+        );
+        assert_eq!(
+            err("while 1 { output(1);"),
+            "line 1: expected '}', found end of input"
+        );
+        assert_eq!(
+            err("let x = output(1);"),
+            "line 1: output() is a statement, not a value"
+        );
+        assert_eq!(
+            err("fn f() {}\nfn f() {}"),
+            "line 2: function f is already defined"
+        );
+        assert_eq!(err("let a = 1 @ 2;"), "line 1: unexpected character '@'");
+    }
+}
@@ -1,5 +1,46 @@
 use std::convert::TryFrom;
 use std::str::FromStr;
+use std::sync::Arc;
+
+mod asm;
+mod async_vm;
+mod device;
+mod dump;
+mod input_log;
+mod lang;
+mod link;
+mod optimize;
+mod program;
+mod registry;
+mod scheduler;
+mod search;
+mod session;
+mod taint;
+mod trace;
+
+pub use asm::{assemble, assemble_object, assemble_with, AsmError, STDLIB};
+pub use async_vm::{channel, sleep, AsyncVm, Executor, Receiver, Sender, Sink, Source};
+pub use device::Device;
+pub use dump::Dump;
+pub use input_log::{InputLog, Player, Recorder};
+pub use lang::{compile, compile_to_asm, CompileError};
+pub use link::{link, LinkError, Object};
+pub use optimize::{optimize, Optimization};
+pub use program::{ParseError, Program};
+pub use registry::{Handler, Instruction, Param, Registry};
+pub use scheduler::{ScheduleError, Scheduler};
+pub use search::{Candidate, Outcome, Search};
+pub use session::{session, Framing};
+pub use taint::{taint, Origin, Origins, Taint, TaintedBranch, TaintedOutput};
+pub use trace::{replay, Divergence, Trace, TraceEntry};
+
+/// Upper bound on the number of memory cells a program may grow to. Accesses
+/// beyond this are reported as [`Error::InvalidAddr`] rather than attempting
+/// the allocation
+pub const MAX_MEMORY: usize = 1 << 24;
+
+/// Maximum number of parameters a single instruction can take
+pub const MAX_PARAMS: usize = 4;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Mode {
@@ -13,11 +54,27 @@ pub enum Error {
     InvalidData,
     InvalidInstr(usize, isize),
     InvalidAddr(usize),
+    NegativeAddr(isize),
     InvalidMode(usize, isize),
+    /// An input instruction found no input. `ip` is left pointing at the
+    /// instruction, so the machine can be resumed once input is available
     NoInput,
     Halted,
 }
 
+/// Decoded parameters of a custom instruction
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Params {
+    len: usize,
+    modes: [Mode; MAX_PARAMS],
+}
+
+impl Params {
+    pub fn as_slice(&self) -> &[Mode] {
+        &self.modes[..self.len]
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Opcode {
     Add(Mode, Mode, Mode),
@@ -30,10 +87,33 @@ pub enum Opcode {
     Eq(Mode, Mode, Mode),
     Offset(Mode),
     Halt,
+    /// An instruction from the [`Registry`] attached to the machine, with its
+    /// opcode and mnemonic
+    Custom(isize, &'static str, Params),
+}
+
+/// How strictly instructions are checked when they are decoded
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DecodeMode {
+    /// Accept anything the original decoder accepted: only the last digit
+    /// selects a built-in opcode, any value ending in 99 halts, and mode
+    /// digits that aren't used by the instruction are ignored
+    #[default]
+    Lenient,
+    /// Reject unknown two-digit opcodes, immediate-mode write targets, stray
+    /// mode digits and instructions that run past the end of memory
+    Strict,
+}
+
+/// Outcome of executing a single instruction with [`Vm::step`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Step {
+    Continue,
+    Output(isize),
 }
 
 impl Opcode {
-    fn pretty_print(self, vm: &Vm) {
+    fn pretty_print(self) {
         use Opcode::*;
         match self {
             Add(a, b, c) => println!("[{:?}] = {:?} + {:?}", c, a, b),
@@ -46,7 +126,114 @@ impl Opcode {
             Input(cell) => println!("[{:?}] = input", cell),
             Output(cell) => println!("output {:?}", cell),
             Offset(m) => println!("offset {:?}", m),
+            Custom(_, name, params) => println!("{} {:?}", name, params.as_slice()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        use Opcode::*;
+        match self {
+            Add(..) => "add",
+            Mul(..) => "mul",
+            Input(_) => "input",
+            Output(_) => "output",
+            Jnz(..) => "jnz",
+            Jz(..) => "jz",
+            Lt(..) => "lt",
+            Eq(..) => "eq",
+            Offset(_) => "offset",
+            Halt => "halt",
+            Custom(_, name, _) => name,
+        }
+    }
+
+    /// Parameters of the instruction, in the order they appear in memory
+    pub fn params(&self) -> Vec<Mode> {
+        use Opcode::*;
+        match *self {
+            Add(a, b, c) | Mul(a, b, c) | Lt(a, b, c) | Eq(a, b, c) => vec![a, b, c],
+            Jnz(a, b) | Jz(a, b) => vec![a, b],
+            Input(a) | Output(a) | Offset(a) => vec![a],
+            Halt => Vec::new(),
+            Custom(_, _, params) => params.as_slice().to_vec(),
+        }
+    }
+
+    /// Number of memory cells taken up by the encoded instruction
+    pub fn size(&self) -> usize {
+        1 + self.params().len()
+    }
+
+    /// Two-digit opcode of the instruction, without any mode digits
+    pub fn code(&self) -> isize {
+        use Opcode::*;
+        match self {
+            Add(..) => 1,
+            Mul(..) => 2,
+            Input(_) => 3,
+            Output(_) => 4,
+            Jnz(..) => 5,
+            Jz(..) => 6,
+            Lt(..) => 7,
+            Eq(..) => 8,
+            Offset(_) => 9,
+            Halt => 99,
+            Custom(code, ..) => *code,
+        }
+    }
+
+    /// Encode the instruction back into memory cells. Decoding the result
+    /// yields the same instruction again
+    pub fn encode(&self) -> Vec<isize> {
+        let params = self.params();
+        let mut instr = self.code();
+        let mut out = Vec::with_capacity(1 + params.len());
+        out.push(0);
+        for (n, p) in params.iter().enumerate() {
+            let (mode, value) = match *p {
+                Mode::Position(x) => (0, x as isize),
+                Mode::Immediate(x) => (1, x),
+                Mode::Relative(x) => (2, x),
+            };
+            instr += mode * 10isize.pow(n as u32 + 2);
+            out.push(value);
+        }
+        out[0] = instr;
+        out
+    }
+}
+
+/// Parameter kinds of the built-in instruction with the two-digit opcode `code`
+fn builtin_params(code: isize) -> Option<&'static [Param]> {
+    use Param::*;
+    match code {
+        1 | 2 | 7 | 8 => Some(&[Read, Read, Write]),
+        3 => Some(&[Write]),
+        4 | 9 => Some(&[Read]),
+        5 | 6 => Some(&[Read, Read]),
+        99 => Some(&[]),
+        _ => None,
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Mode::Position(x) => write!(f, "{}", x),
+            Mode::Immediate(x) => write!(f, "#{}", x),
+            Mode::Relative(x) => write!(f, "rel({})", x),
+        }
+    }
+}
+
+/// Assembly-style rendering used by the disassembler, e.g. `add 4, #3, rel(-1)`
+impl std::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())?;
+        for (i, p) in self.params().iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, p)?;
         }
+        Ok(())
     }
 }
 
@@ -54,7 +241,17 @@ impl Opcode {
 pub struct Vm {
     pub data: Vec<isize>,
     pub ip: usize,
-    base: usize,
+    pub base: usize,
+    /// Address of the instruction being executed, which custom handlers
+    /// may move `ip` away from
+    instr: usize,
+    /// Address of an input instruction that found no input, so devices
+    /// aren't ticked again when it is retried
+    starved: Option<usize>,
+    registry: Arc<Registry>,
+    decode_mode: DecodeMode,
+    devices: device::Devices,
+    trace: Option<Trace>,
 }
 
 impl Vm {
@@ -63,193 +260,332 @@ impl Vm {
             data,
             ip: 0,
             base: 0,
+            instr: 0,
+            starved: None,
+            registry: Arc::default(),
+            decode_mode: DecodeMode::default(),
+            devices: device::Devices::default(),
+            trace: None,
         }
     }
 
-    fn read_param(&mut self, mode_flag: isize) -> Result<Mode, Error> {
-        let &a = self.data.get(self.ip).ok_or(Error::InvalidAddr(self.ip))?;
-        self.ip += 1;
+    /// Attach a set of custom instructions to the machine. Clones of the
+    /// machine share the same registry
+    pub fn set_registry(&mut self, registry: Arc<Registry>) {
+        self.registry = registry;
+    }
+
+    pub fn registry(&self) -> &Arc<Registry> {
+        &self.registry
+    }
+
+    pub fn set_decode_mode(&mut self, mode: DecodeMode) {
+        self.decode_mode = mode;
+    }
+
+    pub fn decode_mode(&self) -> DecodeMode {
+        self.decode_mode
+    }
+
+    fn read_param(&self, addr: usize, mode_flag: isize) -> Result<Mode, Error> {
+        let &a = self.data.get(addr).ok_or(Error::InvalidAddr(addr))?;
         match mode_flag {
             0 => Ok(Mode::Position(a as usize)),
             1 => Ok(Mode::Immediate(a)),
             2 => Ok(Mode::Relative(a)),
-            _ => Err(Error::InvalidMode(self.ip - 1, mode_flag)),
+            _ => Err(Error::InvalidMode(addr, mode_flag)),
         }
     }
 
-    fn read_position(&mut self) -> Result<usize, Error> {
-        let &a = self.data.get(self.ip).ok_or(Error::InvalidAddr(self.ip))?;
-        self.ip += 1;
-        Ok(a as usize)
+    /// Check the instruction `instr` stored at `addr` against the rules of
+    /// [`DecodeMode::Strict`]
+    fn check_strict(&self, addr: usize, instr: isize) -> Result<(), Error> {
+        let code = instr % 100;
+        let params = match self.registry.get(code) {
+            Some(custom) => &custom.params[..],
+            None => builtin_params(code).ok_or(Error::InvalidInstr(addr, instr))?,
+        };
+        if instr < 0 || instr / 10isize.pow(params.len() as u32 + 2) != 0 {
+            return Err(Error::InvalidInstr(addr, instr));
+        }
+        if addr + params.len() >= self.data.len() {
+            return Err(Error::InvalidAddr(self.data.len()));
+        }
+        for (n, param) in params.iter().enumerate() {
+            let mode = (instr / 10isize.pow(n as u32 + 2)) % 10;
+            if *param == Param::Write && mode == 1 {
+                return Err(Error::InvalidMode(addr + 1 + n, mode));
+            }
+        }
+        Ok(())
     }
 
-    fn opcode(&mut self) -> Result<Opcode, Error> {
-        let &instr = self.data.get(self.ip).ok_or(Error::InvalidAddr(self.ip))?;
-        // if instr
-        self.ip += 1;
-        let a = (instr / 10000) % 10;
-        let b = (instr / 1000) % 10;
-        let c = (instr / 100) % 10;
-        // assert_eq!(a, 0, "third parameter must be in position mode!");
-        // if a != 0 {
-        //     return Err(Error::InvalidMode(self.ip-1, a));
-        // }
+    /// Decode the instruction stored at `addr` without executing it
+    pub fn decode(&self, addr: usize) -> Result<Opcode, Error> {
+        let &instr = self.data.get(addr).ok_or(Error::InvalidAddr(addr))?;
+        if self.decode_mode == DecodeMode::Strict {
+            self.check_strict(addr, instr)?;
+        }
+        // Mode digit of the n-th parameter
+        let mode = |n: u32| (instr / 10isize.pow(n + 2)) % 10;
+        let param = |n: u32| self.read_param(addr + 1 + n as usize, mode(n));
         if instr % 100 == 99 {
             return Ok(Opcode::Halt);
         }
-        match instr % 10 {
-            1 => Ok(Opcode::Add(
-                self.read_param(c)?,
-                self.read_param(b)?,
-                self.read_param(a)?,
-            )),
-            2 => Ok(Opcode::Mul(
-                self.read_param(c)?,
-                self.read_param(b)?,
-                self.read_param(a)?,
-            )),
-            3 => Ok(Opcode::Input(self.read_param(c)?)),
-            4 => Ok(Opcode::Output(self.read_param(c)?)),
-            5 => Ok(Opcode::Jnz(self.read_param(c)?, self.read_param(b)?)),
-            6 => Ok(Opcode::Jz(self.read_param(c)?, self.read_param(b)?)),
-            7 => Ok(Opcode::Lt(
-                self.read_param(c)?,
-                self.read_param(b)?,
-                self.read_param(a)?,
-            )),
-            8 => Ok(Opcode::Eq(
-                self.read_param(c)?,
-                self.read_param(b)?,
-                self.read_param(a)?,
-            )),
-            9 => Ok(Opcode::Offset(self.read_param(c)?)),
-            _ => Err(Error::InvalidInstr(self.ip - 1, instr)),
-        }
-    }
-
-    fn fetch(&mut self, mode: Mode) -> Result<isize, Error> {
-        match mode {
-            Mode::Immediate(i) => Ok(i),
-            Mode::Position(idx) => {
-                // self.data.get(idx).copied().ok_or(Error::InvalidAddr(idx))
-                Ok(self.get_or_extend(idx))
+        if let Some(custom) = self.registry.get(instr % 100) {
+            let mut params = Params {
+                len: custom.arity(),
+                modes: [Mode::Immediate(0); MAX_PARAMS],
+            };
+            for n in 0..params.len {
+                // Handlers write through their parameters with
+                // Vm::store_or_extend, which can't tell where an immediate
+                // destination came from, so reject it here in every mode
+                if custom.params[n] == Param::Write && mode(n as u32) == 1 {
+                    return Err(Error::InvalidMode(addr + 1 + n, 1));
+                }
+                params.modes[n] = param(n as u32)?;
             }
+            return Ok(Opcode::Custom(custom.code, custom.name, params));
+        }
+        match instr % 10 {
+            1 => Ok(Opcode::Add(param(0)?, param(1)?, param(2)?)),
+            2 => Ok(Opcode::Mul(param(0)?, param(1)?, param(2)?)),
+            3 => Ok(Opcode::Input(param(0)?)),
+            4 => Ok(Opcode::Output(param(0)?)),
+            5 => Ok(Opcode::Jnz(param(0)?, param(1)?)),
+            6 => Ok(Opcode::Jz(param(0)?, param(1)?)),
+            7 => Ok(Opcode::Lt(param(0)?, param(1)?, param(2)?)),
+            8 => Ok(Opcode::Eq(param(0)?, param(1)?, param(2)?)),
+            9 => Ok(Opcode::Offset(param(0)?)),
+            _ => Err(Error::InvalidInstr(addr, instr)),
+        }
+    }
+
+    fn opcode(&mut self) -> Result<Opcode, Error> {
+        let op = self.decode(self.ip)?;
+        self.ip += op.size();
+        Ok(op)
+    }
+
+    /// Resolve `off` against the relative base
+    fn relative(&self, off: isize) -> Result<usize, Error> {
+        let addr = (self.base as isize).wrapping_add(off);
+        usize::try_from(addr).map_err(|_| Error::NegativeAddr(addr))
+    }
+
+    /// Read the value of a parameter, growing memory if needed
+    pub fn fetch(&mut self, mode: Mode) -> Result<isize, Error> {
+        let value = match mode {
+            Mode::Immediate(i) => i,
+            Mode::Position(idx) => self.get_or_extend(idx)?,
             Mode::Relative(off) => {
-                let base = usize::try_from(self.base as isize + off).expect(&format!(
-                    "relative addr out of bounds: {}+{}",
-                    self.base, off
-                ));
-                Ok(self.get_or_extend(base))
-                // self.data.get(base).copied().ok_or(Error::InvalidAddr(base))
+                let addr = self.relative(off)?;
+                self.get_or_extend(addr)?
             }
-        }
+        };
+        self.note(|e| e.operands.push(value));
+        Ok(value)
     }
 
-    pub fn decompile(mut self) {
-        while self.ip < self.data.len() {
-            print!("{}: ", self.ip);
-            match self.opcode() {
-                Ok(Opcode::Offset(off)) => {
-                    self.base =
-                        usize::try_from(self.base as isize + self.fetch(off).unwrap()).unwrap()
-                }
-                Ok(op) => println!("{:?}", op),
-                _ => {
-                    self.ip += 1;
+    /// Linear sweep disassembly of the whole memory image. Cells that don't
+    /// decode to a valid instruction are skipped one at a time
+    pub fn disassemble(&self) -> Vec<(usize, Opcode)> {
+        let mut out = Vec::new();
+        let mut ip = 0;
+        while ip < self.data.len() {
+            match self.decode(ip) {
+                Ok(op) => {
+                    out.push((ip, op));
+                    ip += op.size();
                 }
+                Err(_) => ip += 1,
             }
         }
+        out
+    }
+
+    pub fn decompile(self) {
+        for (ip, op) in self.disassemble() {
+            println!("{}: {}", ip, op);
+        }
     }
 
-    fn get_or_extend(&mut self, loc: usize) -> isize {
+    /// Set the cell at `addr`, growing memory to just hold it. Used to patch
+    /// a program before it runs, so it is bounded by [`MAX_MEMORY`] like any
+    /// other access
+    pub fn patch(&mut self, addr: usize, value: isize) -> Result<(), Error> {
+        if addr >= MAX_MEMORY {
+            return Err(Error::InvalidAddr(addr));
+        }
+        if addr >= self.data.len() {
+            self.data.resize(addr + 1, 0);
+        }
+        self.data[addr] = value;
+        Ok(())
+    }
+
+    fn extend(&mut self, loc: usize) -> Result<(), Error> {
+        if loc >= MAX_MEMORY {
+            return Err(Error::InvalidAddr(loc));
+        }
         if loc >= self.data.len() {
-            self.data
-                .extend(std::iter::repeat(0).take(2 * (loc - self.data.len() + 1)));
+            let len = (self.data.len() + 2 * (loc - self.data.len() + 1)).min(MAX_MEMORY);
+            self.data.resize(len, 0);
         }
-        assert!(loc < self.data.len());
-        self.data[loc]
+        Ok(())
     }
 
-    fn store_or_extend(&mut self, loc: Mode, data: isize) {
+    fn get_or_extend(&mut self, loc: usize) -> Result<isize, Error> {
+        if let Some(x) = self.devices.read(loc) {
+            return Ok(x);
+        }
+        self.extend(loc)?;
+        Ok(self.data[loc])
+    }
+
+    /// Write `data` to the address named by a parameter, growing memory if
+    /// needed. The decoder never hands a custom instruction an immediate
+    /// write parameter, so an immediate `loc` means a handler wrote through
+    /// a read parameter, and is reported at the instruction's address
+    pub fn store_or_extend(&mut self, loc: Mode, data: isize) -> Result<(), Error> {
+        self.store(self.instr, loc, data)
+    }
+
+    /// Write `data` to the address named by the parameter stored at `param`
+    fn store(&mut self, param: usize, loc: Mode, data: isize) -> Result<(), Error> {
         let loc = match loc {
             Mode::Position(x) => x,
-            Mode::Relative(off) => usize::try_from(self.base as isize + off).unwrap(),
-            _ => unimplemented!(),
+            Mode::Relative(off) => self.relative(off)?,
+            Mode::Immediate(_) => return Err(Error::InvalidMode(param, 1)),
         };
-        if loc >= self.data.len() {
-            self.data
-                .extend(std::iter::repeat(0).take(2 * (loc - self.data.len() + 1)));
+        self.note(|e| e.write = Some((loc, data)));
+        if self.devices.write(loc, data) {
+            return Ok(());
         }
-        assert!(loc < self.data.len());
+        self.extend(loc)?;
         self.data[loc] = data;
+        Ok(())
     }
 
-    pub fn run<I: Iterator<Item = isize>>(
+    /// Execute a single instruction. `input` is called at most once, when the
+    /// instruction needs an input value
+    pub fn step<F: FnMut() -> Option<isize>>(
         &mut self,
-        mut input: I,
+        mut input: F,
         verbose: bool,
-    ) -> Result<isize, Error> {
-        while self.ip < self.data.len() {
-            let ip = self.ip;
-            let op = self.opcode()?;
-            assert!(self.ip > ip);
-
-            if verbose {
-                print!("{:3}: ", ip);
-                op.pretty_print(&self);
+    ) -> Result<Step, Error> {
+        if self.ip >= self.data.len() {
+            return Err(Error::Halted);
+        }
+        if !self.devices.is_empty() && self.starved != Some(self.ip) {
+            if let Some(vector) = self.devices.tick() {
+                self.interrupt(vector)?;
             }
+        }
+        self.starved = None;
+        let ip = self.ip;
+        let op = self.opcode()?;
 
-            match op {
-                Opcode::Halt => {
-                    return Err(Error::Halted);
-                }
-                Opcode::Add(a, b, c) => {
-                    let a = self.fetch(a)?;
-                    let b = self.fetch(b)?;
-                    self.store_or_extend(c, a + b);
-                }
-                Opcode::Mul(a, b, c) => {
-                    let a = self.fetch(a)?;
-                    let b = self.fetch(b)?;
-                    self.store_or_extend(c, a * b);
-                }
-                Opcode::Input(idx) => {
-                    self.store_or_extend(idx, input.next().ok_or(Error::NoInput)?);
-                }
-                Opcode::Output(mode) => {
-                    return self.fetch(mode);
-                }
-                Opcode::Jnz(a, b) => {
-                    let a = self.fetch(a)?;
-                    let b = self.fetch(b)?;
-                    if a != 0 {
-                        self.ip = b as usize;
-                    }
-                }
-                Opcode::Jz(a, b) => {
-                    let a = self.fetch(a)?;
-                    let b = self.fetch(b)?;
-                    if a == 0 {
-                        self.ip = b as usize;
-                    }
-                }
-                Opcode::Lt(a, b, c) => {
-                    let a = self.fetch(a)?;
-                    let b = self.fetch(b)?;
-                    self.store_or_extend(c, if a < b { 1 } else { 0 });
+        if verbose {
+            print!("{:3}: ", ip);
+            op.pretty_print();
+        }
+
+        self.instr = ip;
+        if let Some(trace) = &mut self.trace {
+            trace.begin(ip, op, self.base);
+        }
+        let result = self.execute(ip, op, &mut input);
+        if let Some(trace) = &mut self.trace {
+            trace.end(&result);
+        }
+        if result == Err(Error::NoInput) {
+            // Nothing was executed, so the input can be retried once there
+            // is some
+            self.ip = ip;
+            self.starved = Some(ip);
+        }
+        result
+    }
+
+    fn execute<F: FnMut() -> Option<isize>>(
+        &mut self,
+        ip: usize,
+        op: Opcode,
+        input: &mut F,
+    ) -> Result<Step, Error> {
+        match op {
+            Opcode::Halt => {
+                return Err(Error::Halted);
+            }
+            Opcode::Add(a, b, c) => {
+                let a = self.fetch(a)?;
+                let b = self.fetch(b)?;
+                self.store(ip + 3, c, a.wrapping_add(b))?;
+            }
+            Opcode::Mul(a, b, c) => {
+                let a = self.fetch(a)?;
+                let b = self.fetch(b)?;
+                self.store(ip + 3, c, a.wrapping_mul(b))?;
+            }
+            Opcode::Input(idx) => {
+                let x = input().ok_or(Error::NoInput)?;
+                self.note(|e| e.input = Some(x));
+                self.store(ip + 1, idx, x)?;
+            }
+            Opcode::Output(mode) => {
+                return self.fetch(mode).map(Step::Output);
+            }
+            Opcode::Jnz(a, b) => {
+                let a = self.fetch(a)?;
+                let b = self.fetch(b)?;
+                if a != 0 {
+                    self.ip = b as usize;
                 }
-                Opcode::Eq(a, b, c) => {
-                    let a = self.fetch(a)?;
-                    let b = self.fetch(b)?;
-                    self.store_or_extend(c, if a == b { 1 } else { 0 });
+            }
+            Opcode::Jz(a, b) => {
+                let a = self.fetch(a)?;
+                let b = self.fetch(b)?;
+                if a == 0 {
+                    self.ip = b as usize;
                 }
-                Opcode::Offset(a) => {
-                    let off = self.fetch(a)?;
-                    self.base = usize::try_from(self.base as isize + off).unwrap();
+            }
+            Opcode::Lt(a, b, c) => {
+                let a = self.fetch(a)?;
+                let b = self.fetch(b)?;
+                self.store(ip + 3, c, if a < b { 1 } else { 0 })?;
+            }
+            Opcode::Eq(a, b, c) => {
+                let a = self.fetch(a)?;
+                let b = self.fetch(b)?;
+                self.store(ip + 3, c, if a == b { 1 } else { 0 })?;
+            }
+            Opcode::Offset(a) => {
+                let off = self.fetch(a)?;
+                self.base = self.relative(off)?;
+            }
+            Opcode::Custom(code, _, params) => {
+                let handler = self.registry.get(code).map(|i| i.handler.clone());
+                let handler = handler.ok_or(Error::InvalidInstr(ip, code))?;
+                if let Some(x) = handler(self, params.as_slice())? {
+                    return Ok(Step::Output(x));
                 }
             }
         }
-        Err(Error::Halted)
+        Ok(Step::Continue)
+    }
+
+    pub fn run<I: Iterator<Item = isize>>(
+        &mut self,
+        mut input: I,
+        verbose: bool,
+    ) -> Result<isize, Error> {
+        loop {
+            if let Step::Output(x) = self.step(|| input.next(), verbose)? {
+                return Ok(x);
+            }
+        }
     }
 
     pub fn run_fn<F: FnMut() -> isize>(
@@ -257,81 +593,34 @@ impl Vm {
         mut input: F,
         verbose: bool,
     ) -> Result<isize, Error> {
-        while self.ip < self.data.len() {
-            let ip = self.ip;
-            let op = self.opcode()?;
-            assert!(self.ip > ip);
-
-            if verbose {
-                print!("{:3}: ", ip);
-                op.pretty_print(&self);
-            }
+        self.run(std::iter::from_fn(|| Some(input())), verbose)
+    }
+}
 
-            match op {
-                Opcode::Halt => {
-                    return Err(Error::Halted);
-                }
-                Opcode::Add(a, b, c) => {
-                    let a = self.fetch(a)?;
-                    let b = self.fetch(b)?;
-                    self.store_or_extend(c, a + b);
-                }
-                Opcode::Mul(a, b, c) => {
-                    let a = self.fetch(a)?;
-                    let b = self.fetch(b)?;
-                    self.store_or_extend(c, a * b);
-                }
-                Opcode::Input(idx) => self.store_or_extend(idx, input()),
-                Opcode::Output(mode) => {
-                    return self.fetch(mode);
-                }
-                Opcode::Jnz(a, b) => {
-                    let a = self.fetch(a)?;
-                    let b = self.fetch(b)?;
-                    if a != 0 {
-                        self.ip = b as usize;
-                    }
-                }
-                Opcode::Jz(a, b) => {
-                    let a = self.fetch(a)?;
-                    let b = self.fetch(b)?;
-                    if a == 0 {
-                        self.ip = b as usize;
-                    }
-                }
-                Opcode::Lt(a, b, c) => {
-                    let a = self.fetch(a)?;
-                    let b = self.fetch(b)?;
-                    self.store_or_extend(c, if a < b { 1 } else { 0 });
-                }
-                Opcode::Eq(a, b, c) => {
-                    let a = self.fetch(a)?;
-                    let b = self.fetch(b)?;
-                    self.store_or_extend(c, if a == b { 1 } else { 0 });
-                }
-                Opcode::Offset(a) => {
-                    let off = self.fetch(a)?;
-                    self.base = usize::try_from(self.base as isize + off).unwrap();
-                }
-            }
-        }
-        Err(Error::Halted)
+/// Parse a memory patch written `ADDR=VALUE`, as given to [`Vm::patch`].
+/// Addresses beyond [`MAX_MEMORY`] are reported as [`Error::InvalidAddr`]
+pub fn parse_patch(text: &str) -> Result<(usize, isize), Error> {
+    let mut parts = text.splitn(2, '=');
+    let addr = parts.next().and_then(|a| a.trim().parse::<usize>().ok());
+    let value = parts.next().and_then(|v| v.trim().parse().ok());
+    match (addr, value) {
+        (Some(addr), _) if addr >= MAX_MEMORY => Err(Error::InvalidAddr(addr)),
+        (Some(addr), Some(value)) => Ok((addr, value)),
+        _ => Err(Error::InvalidData),
     }
 }
 
+/// Parses the [`Program`] format, dropping any metadata. A program with no
+/// cells is invalid. Parse the program itself to find where an invalid
+/// program went wrong
 impl FromStr for Vm {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let data = s
-            .split(',')
-            .map(|s| s.trim().parse::<isize>().map_err(|_| Error::InvalidData))
-            .collect::<Result<_, _>>()?;
-
-        Ok(Vm {
-            data,
-            ip: 0,
-            base: 0,
-        })
+        let program = s.parse::<Program>().map_err(|_| Error::InvalidData)?;
+        if program.data.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        Ok(Vm::from(program))
     }
 }
 
@@ -365,11 +654,11 @@ mod test {
         let ex = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99";
         let mut vm = ex.parse::<Vm>().unwrap();
         vm.clone().decompile();
-        assert_eq!(vm.run(std::iter::repeat(7), true), Ok(999));
+        assert_eq!(vm.run(std::iter::repeat(7), false), Ok(999));
         let mut vm = ex.parse::<Vm>().unwrap();
-        assert_eq!(vm.run(std::iter::repeat(8), true), Ok(1000));
+        assert_eq!(vm.run(std::iter::repeat(8), false), Ok(1000));
         let mut vm = ex.parse::<Vm>().unwrap();
-        assert_eq!(vm.run(std::iter::repeat(9), true), Ok(1001));
+        assert_eq!(vm.run(std::iter::repeat(9), false), Ok(1001));
     }
 
     #[test]
@@ -377,12 +666,12 @@ mod test {
         let ex = "109,19,99";
         let mut vm = ex.parse::<Vm>().unwrap();
         // vm.clone().decompile();
-        assert_eq!(vm.run(std::iter::repeat(0), true), Err(Error::Halted));
+        assert_eq!(vm.run(std::iter::repeat(0), false), Err(Error::Halted));
         assert_eq!(vm.base, 19);
 
         let ex = "104,1125899906842624,99";
         let mut vm = ex.parse::<Vm>().unwrap();
-        assert_eq!(vm.run(std::iter::repeat(0), true), Ok(1125899906842624));
+        assert_eq!(vm.run(std::iter::repeat(0), false), Ok(1125899906842624));
     }
 
     #[test]
@@ -392,6 +681,89 @@ mod test {
         // output cell 0
         let ex = "109,9,2105,4,-1,4,0,99,5";
         let mut vm = ex.parse::<Vm>().unwrap();
-        assert_eq!(vm.run(std::iter::repeat(0), true), Ok(109));
+        assert_eq!(vm.run(std::iter::repeat(0), false), Ok(109));
+    }
+
+    #[test]
+    fn faults() {
+        let mut vm = "204,-1,99".parse::<Vm>().unwrap();
+        assert_eq!(
+            vm.run(std::iter::empty(), false),
+            Err(Error::NegativeAddr(-1))
+        );
+
+        let mut vm = "109,-3,99".parse::<Vm>().unwrap();
+        assert_eq!(
+            vm.run(std::iter::empty(), false),
+            Err(Error::NegativeAddr(-3))
+        );
+
+        let mut vm = "11101,1,2,3,99".parse::<Vm>().unwrap();
+        assert_eq!(
+            vm.run(std::iter::empty(), false),
+            Err(Error::InvalidMode(3, 1))
+        );
+
+        let mut vm = "4,-7,99".parse::<Vm>().unwrap();
+        assert_eq!(
+            vm.run(std::iter::empty(), false),
+            Err(Error::InvalidAddr(-7isize as usize))
+        );
+    }
+
+    #[test]
+    fn empty_program() {
+        assert_eq!("".parse::<Vm>(), Err(Error::InvalidData));
+        assert_eq!("# name: nothing\n\n".parse::<Vm>(), Err(Error::InvalidData));
+        assert_eq!("99".parse::<Vm>().map(|vm| vm.data), Ok(vec![99]));
+    }
+
+    #[test]
+    fn patches() {
+        assert_eq!(parse_patch(" 1 = -12"), Ok((1, -12)));
+        assert_eq!(parse_patch("1=x"), Err(Error::InvalidData));
+        assert_eq!(parse_patch("1"), Err(Error::InvalidData));
+        assert_eq!(
+            parse_patch("99999999999=1"),
+            Err(Error::InvalidAddr(99999999999))
+        );
+
+        let mut vm = Vm::new(vec![99]);
+        assert_eq!(vm.patch(4, 7), Ok(()));
+        assert_eq!(vm.data, vec![99, 0, 0, 0, 7]);
+        assert_eq!(vm.patch(MAX_MEMORY, 1), Err(Error::InvalidAddr(MAX_MEMORY)));
+        assert_eq!(vm.data.len(), 5);
+    }
+
+    #[test]
+    fn strict_decoding() {
+        let strict = |program: &str| {
+            let mut vm = program.parse::<Vm>().unwrap();
+            vm.set_decode_mode(DecodeMode::Strict);
+            vm.decode(0)
+        };
+        let lenient = |program: &str| program.parse::<Vm>().unwrap().decode(0);
+
+        // Unknown two-digit opcode
+        assert_eq!(strict("11,0,0,0"), Err(Error::InvalidInstr(0, 11)));
+        assert!(lenient("11,0,0,0").is_ok());
+        assert_eq!(strict("1199"), Err(Error::InvalidInstr(0, 1199)));
+        assert_eq!(lenient("1199"), Ok(Opcode::Halt));
+        assert_eq!(strict("-1,0"), Err(Error::InvalidInstr(0, -1)));
+
+        // Immediate-mode write target
+        assert_eq!(strict("10001,0,0,0"), Err(Error::InvalidMode(3, 1)));
+        assert_eq!(strict("103,0"), Err(Error::InvalidMode(1, 1)));
+        assert!(lenient("10001,0,0,0").is_ok());
+
+        // Stray high mode digits
+        assert_eq!(strict("1104,5"), Err(Error::InvalidInstr(0, 1104)));
+        assert_eq!(strict("100001,0,0,0"), Err(Error::InvalidInstr(0, 100001)));
+        assert_eq!(lenient("1104,5"), Ok(Opcode::Output(Mode::Immediate(5))));
+
+        // Parameters beyond the end of memory
+        assert_eq!(strict("1,0,0"), Err(Error::InvalidAddr(3)));
+        assert_eq!(strict("1,0,0,0"), lenient("1,0,0,0"));
+        assert_eq!(strict("21102,2,3,-1"), lenient("21102,2,3,-1"));
     }
 }
@@ -0,0 +1,295 @@
+//! Relocatable objects and the linker that combines them into a program.
+//!
+//! An [`Object`] is a module assembled as if it were loaded at address 0,
+//! along with the cells that need patching once its real address is known:
+//!
+//! - relocations, cells holding an address within the object, such as
+//!   `Position` operands and jump targets that refer to its labels
+//! - imports, cells holding an offset from a symbol exported by another
+//!   object
+//!
+//! [`link`] lays objects out one after the other, starting with the first at
+//! address 0, and patches every relocation and import. The linker also
+//! defines `end`, the first address after the last object, which a program
+//! can use to put its stack after all of the code.
+//!
+//! ```
+//! use intcode::{assemble_object, link, Vm, STDLIB};
+//!
+//! let main = assemble_object(
+//!     "       extern std_div, end
+//!             offset #end
+//!             push #91
+//!             push #7
+//!             call std_div
+//!             result rel(-2)
+//!             drop 1
+//!             output rel(-1)
+//!             halt",
+//! )
+//! .unwrap();
+//! let std = assemble_object(STDLIB).unwrap();
+//!
+//! let mut vm = Vm::new(link(&[main, std]).unwrap());
+//! assert_eq!(vm.run(std::iter::empty(), false), Ok(13));
+//! ```
+//!
+//! Objects are saved as text, one section per line:
+//!
+//! ```text
+//! code 1106,0,3,1105,1,0
+//! reloc 2
+//! export start 3
+//! import 5 std_div
+//! ```
+use super::Error;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Symbol defined by the linker, at the first address after all objects
+const END: &str = "end";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Object {
+    /// Contents of the object, as if it were loaded at address 0
+    pub code: Vec<isize>,
+    /// Cells holding an address within the object
+    pub relocations: Vec<usize>,
+    /// Labels visible to other objects, with their address in the object
+    pub exports: BTreeMap<String, usize>,
+    /// Cells to which the address of a symbol from another object is added
+    pub imports: Vec<(usize, String)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LinkError {
+    /// A symbol is exported by more than one object
+    Duplicate(String),
+    /// A symbol is imported, but no object exports it
+    Undefined(String),
+}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LinkError::Duplicate(s) => write!(f, "symbol '{}' is defined more than once", s),
+            LinkError::Undefined(s) => write!(f, "undefined symbol '{}'", s),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Combine objects into a single program, which starts executing at the
+/// beginning of the first object
+pub fn link(objects: &[Object]) -> Result<Vec<isize>, LinkError> {
+    let mut symbols = BTreeMap::new();
+    let mut program = Vec::new();
+    let mut bases = Vec::new();
+    for object in objects {
+        let base = program.len();
+        for (name, &addr) in &object.exports {
+            if symbols.insert(name.as_str(), base + addr).is_some() || name == END {
+                return Err(LinkError::Duplicate(name.clone()));
+            }
+        }
+        program.extend(&object.code);
+        for &cell in &object.relocations {
+            program[base + cell] += base as isize;
+        }
+        bases.push(base);
+    }
+    symbols.insert(END, program.len());
+
+    for (object, base) in objects.iter().zip(bases) {
+        for (cell, name) in &object.imports {
+            match symbols.get(name.as_str()) {
+                Some(&addr) => program[base + cell] += addr as isize,
+                None => return Err(LinkError::Undefined(name.clone())),
+            }
+        }
+    }
+    Ok(program)
+}
+
+impl Object {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Object> {
+        std::fs::read_to_string(path)?
+            .parse()
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid object"))
+    }
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl std::fmt::Display for Object {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "code {}", join(&self.code))?;
+        writeln!(f, "reloc {}", join(&self.relocations))?;
+        for (name, addr) in &self.exports {
+            writeln!(f, "export {} {}", name, addr)?;
+        }
+        for (cell, name) in &self.imports {
+            writeln!(f, "import {} {}", cell, name)?;
+        }
+        Ok(())
+    }
+}
+
+fn split<T: FromStr>(list: &str) -> Result<Vec<T>, Error> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().map_err(|_| Error::InvalidData))
+        .collect()
+}
+
+impl FromStr for Object {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut object = Object::default();
+        for line in s.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next(), words.next()) {
+                (Some("code"), list, None, None) => object.code = split(list.unwrap_or(""))?,
+                (Some("reloc"), list, None, None) => {
+                    object.relocations = split(list.unwrap_or(""))?
+                }
+                (Some("export"), Some(name), Some(addr), None) => {
+                    let addr = addr.parse().map_err(|_| Error::InvalidData)?;
+                    object.exports.insert(name.to_string(), addr);
+                }
+                (Some("import"), Some(cell), Some(name), None) => {
+                    let cell = cell.parse().map_err(|_| Error::InvalidData)?;
+                    object.imports.push((cell, name.to_string()));
+                }
+                _ => return Err(Error::InvalidData),
+            }
+        }
+        let len = object.code.len();
+        let cells = object
+            .relocations
+            .iter()
+            .chain(object.imports.iter().map(|(c, _)| c));
+        if cells.chain(object.exports.values()).any(|&c| c >= len) {
+            return Err(Error::InvalidData);
+        }
+        Ok(object)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assemble, assemble_object, Vm, STDLIB};
+
+    fn outputs(program: Vec<isize>) -> Vec<isize> {
+        let mut vm = Vm::new(program);
+        std::iter::from_fn(|| vm.run(std::iter::empty(), false).ok()).collect()
+    }
+
+    #[test]
+    fn relocation() {
+        let source = "
+                    export count
+             loop:  output count
+                    add count, #-1, count
+                    jnz count, #loop
+                    add #$, #0, rel(0)      ; absolute, relocated
+                    add #count - loop, #0, rel(1)
+                    halt
+             count: data 3";
+        let object = assemble_object(source).unwrap();
+        assert_eq!(object.relocations, vec![1, 3, 5, 7, 8, 10]);
+        assert_eq!(object.exports["count"], 18);
+
+        // Linked on its own, the object is the same as the program
+        let program = link(std::slice::from_ref(&object)).unwrap();
+        assert_eq!(program, assemble(source).unwrap());
+        assert_eq!(outputs(program), vec![3, 2, 1]);
+
+        // Behind another object, every address moves, but not distances
+        let halt = assemble_object("halt").unwrap();
+        let program = link(&[halt, object]).unwrap();
+        assert_eq!(program[..5], [99, 4, 19, 1001, 19]);
+        assert_eq!((program[11], program[15]), (10, 18));
+    }
+
+    #[test]
+    fn symbols() {
+        let main = assemble_object(
+            "       extern greeting, std_print_str, end
+                    offset #end
+                    push #greeting + 1
+                    call std_print_str
+                    halt",
+        )
+        .unwrap();
+        let data = assemble_object("export greeting\ngreeting: string \"Hello\"").unwrap();
+        let std = assemble_object(STDLIB).unwrap();
+        let program = link(&[main, data, std]).unwrap();
+        assert_eq!(
+            outputs(program),
+            "ello".bytes().map(|b| b as isize).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let a = assemble_object("export x\nx: halt").unwrap();
+        let b = assemble_object("extern y\noutput y").unwrap();
+        assert_eq!(
+            link(&[a.clone(), a.clone()]),
+            Err(LinkError::Duplicate("x".to_string()))
+        );
+        assert_eq!(link(&[a, b]), Err(LinkError::Undefined("y".to_string())));
+        let end = assemble_object("export end\nend: halt").unwrap();
+        assert_eq!(link(&[end]), Err(LinkError::Duplicate("end".to_string())));
+
+        let err = |source: &str| assemble_object(source).unwrap_err().to_string();
+        assert_eq!(
+            err("a: add a + a, #0, 0"),
+            "line 1: cannot relocate 'a + a'"
+        );
+        assert_eq!(err("extern x\noutput #-x"), "line 2: cannot relocate '-x'");
+        assert_eq!(
+            err("extern x\nx: halt"),
+            "line 1: extern 'x' is also defined"
+        );
+        assert_eq!(err("export nope"), "line 1: undefined label 'nope'");
+        assert_eq!(
+            assemble("extern x").unwrap_err().to_string(),
+            "line 1: extern 'x' needs an object"
+        );
+    }
+
+    #[test]
+    fn save_and_load() {
+        let object = assemble_object("extern f\nexport g\ng: jnz #1, #f + 2\njz #0, #g").unwrap();
+        let text = object.to_string();
+        assert_eq!(
+            text,
+            "code 1105,1,2,1106,0,0\nreloc 5\nexport g 0\nimport 2 f\n"
+        );
+        assert_eq!(text.parse::<Object>(), Ok(object.clone()));
+
+        let path = std::env::temp_dir().join(format!("intcode-object-{}", std::process::id()));
+        object.save(&path).unwrap();
+        assert_eq!(Object::load(&path).unwrap(), object);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!("code 1\nreloc 1".parse::<Object>(), Err(Error::InvalidData));
+        assert_eq!("data 1".parse::<Object>(), Err(Error::InvalidData));
+    }
+}
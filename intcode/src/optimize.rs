@@ -0,0 +1,442 @@
+//! A peephole optimizer for program images.
+//!
+//! The control flow graph is recovered by following every path from address
+//! 0, and the reachable instructions are then rewritten:
+//!
+//! - `add` and `mul` of two immediates are folded into a single constant
+//! - `jz #0, x` becomes the unconditional `jnz #1, x`, and jumps that are
+//!   never taken are dropped
+//! - stores that are overwritten before they are ever read are dropped
+//! - unreachable code is removed
+//!
+//! Removing cells moves everything after them, so instructions and data are
+//! only removed when every address in the program can be relocated: all
+//! jumps must have immediate targets, the relative base must not be used, and
+//! the program must not read its own instructions as data. Otherwise only
+//! the in-place rewrites are done. A program that writes over its own
+//! instructions is returned unchanged.
+//!
+//! Accesses through the relative base are assumed to address data rather
+//! than code, as they do in the stack frames of compiled programs.
+use super::{Mode, Opcode, Vm};
+use std::collections::BTreeMap;
+
+/// The optimized program, along with what was changed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Optimization {
+    pub program: Vec<isize>,
+    /// Arithmetic instructions folded into a constant
+    pub folded: usize,
+    /// Conditional jumps that were made unconditional or dropped
+    pub jumps: usize,
+    pub dead_stores: usize,
+    /// Cells of unreachable code that were removed
+    pub unreachable: usize,
+    /// Set if the program writes to its own instructions, in which case it
+    /// is returned unchanged
+    pub self_modifying: bool,
+}
+
+/// Whether a conditional jump is always, never or sometimes taken
+fn taken(op: &Opcode) -> Option<bool> {
+    match *op {
+        Opcode::Jnz(Mode::Immediate(c), _) => Some(c != 0),
+        Opcode::Jz(Mode::Immediate(c), _) => Some(c == 0),
+        _ => None,
+    }
+}
+
+/// Whether the instruction has an immediate write operand, which decodes in
+/// the default mode but faults when executed
+fn faults(op: &Opcode) -> bool {
+    match *op {
+        Opcode::Add(_, _, dst)
+        | Opcode::Mul(_, _, dst)
+        | Opcode::Lt(_, _, dst)
+        | Opcode::Eq(_, _, dst)
+        | Opcode::Input(dst) => {
+            matches!(dst, Mode::Immediate(_))
+        }
+        _ => false,
+    }
+}
+
+/// Addresses read and written by an instruction through position mode
+fn accesses(op: &Opcode) -> (Vec<usize>, Option<usize>) {
+    let params = op.params();
+    let writes = match op {
+        Opcode::Add(..) | Opcode::Mul(..) | Opcode::Lt(..) | Opcode::Eq(..) | Opcode::Input(_) => {
+            params.len() - 1
+        }
+        _ => params.len(),
+    };
+    let reads = params[..writes]
+        .iter()
+        .filter_map(|p| match *p {
+            Mode::Position(a) => Some(a),
+            _ => None,
+        })
+        .collect();
+    let write = params[writes..].iter().find_map(|p| match *p {
+        Mode::Position(a) => Some(a),
+        _ => None,
+    });
+    (reads, write)
+}
+
+/// The instructions reachable from a set of entry points, following jumps
+/// with immediate targets. Memory dumps use this to tell code from data
+pub(crate) struct Cfg {
+    pub(crate) instrs: BTreeMap<usize, Opcode>,
+    /// Start of the reachable instruction covering each cell
+    owner: Vec<Option<usize>>,
+    /// Set if a jump has a target that isn't an immediate
+    indirect: bool,
+    /// Set if two reachable instructions share a cell
+    overlap: bool,
+    /// Addresses where a path ends in an instruction that doesn't decode
+    faulting: Vec<usize>,
+}
+
+impl Cfg {
+    pub(crate) fn build(vm: &Vm, entries: &[usize]) -> Cfg {
+        let len = vm.data.len();
+        let mut cfg = Cfg {
+            instrs: BTreeMap::new(),
+            owner: vec![None; len],
+            indirect: false,
+            overlap: false,
+            faulting: Vec::new(),
+        };
+        let mut work = entries.to_vec();
+        while let Some(addr) = work.pop() {
+            if addr >= len || cfg.instrs.contains_key(&addr) {
+                continue;
+            }
+            // An instruction that doesn't decode faults, and so ends the path
+            let op = match vm.decode(addr) {
+                Ok(op) => op,
+                Err(_) => {
+                    cfg.faulting.push(addr);
+                    continue;
+                }
+            };
+            for cell in &mut cfg.owner[addr..addr + op.size()] {
+                cfg.overlap |= cell.is_some();
+                *cell = Some(addr);
+            }
+            cfg.instrs.insert(addr, op);
+
+            let next = addr + op.size();
+            match op {
+                _ if faults(&op) => {}
+                Opcode::Halt => {}
+                Opcode::Jnz(_, target) | Opcode::Jz(_, target) => {
+                    if taken(&op) != Some(true) {
+                        work.push(next);
+                    }
+                    match target {
+                        _ if taken(&op) == Some(false) => {}
+                        Mode::Immediate(t) if t >= 0 => work.push(t as usize),
+                        // Jumping to a negative address halts the machine
+                        Mode::Immediate(_) => {}
+                        _ => cfg.indirect = true,
+                    }
+                }
+                _ => work.push(next),
+            }
+        }
+        cfg
+    }
+
+    fn is_code(&self, addr: usize) -> bool {
+        self.owner.get(addr).map(Option::is_some).unwrap_or(false)
+    }
+}
+
+/// Whether the value stored by the instruction at `addr` to `dst` is
+/// overwritten before anything can read it, looking along the straight line
+/// of code that follows
+fn overwritten(cfg: &Cfg, addr: usize, dst: usize) -> bool {
+    let mut ip = addr + cfg.instrs[&addr].size();
+    while let Some(op) = cfg.instrs.get(&ip) {
+        let (reads, write) = accesses(op);
+        if reads.contains(&dst) {
+            return false;
+        }
+        if write == Some(dst) {
+            return true;
+        }
+        match op {
+            _ if faults(op) => return false,
+            Opcode::Add(..)
+            | Opcode::Mul(..)
+            | Opcode::Lt(..)
+            | Opcode::Eq(..)
+            | Opcode::Input(_)
+            | Opcode::Output(_) => ip += op.size(),
+            // Memory is observable once the machine halts, and anything
+            // else might branch
+            _ => return false,
+        }
+    }
+    false
+}
+
+pub fn optimize(program: &[isize]) -> Optimization {
+    let vm = Vm::new(program.to_vec());
+    let cfg = Cfg::build(&vm, &[0]);
+    let mut result = Optimization {
+        program: program.to_vec(),
+        ..Optimization::default()
+    };
+
+    // Cells that are read as data, and whether any of them are instructions
+    let mut referenced = vec![false; program.len()];
+    let mut relative = false;
+    for op in cfg.instrs.values() {
+        let (reads, write) = accesses(op);
+        if write.map(|w| cfg.is_code(w)).unwrap_or(false) {
+            result.self_modifying = true;
+            return result;
+        }
+        for a in reads.into_iter().chain(write) {
+            if a < referenced.len() {
+                referenced[a] = true;
+            }
+        }
+        relative |= op.params().iter().any(|p| matches!(p, Mode::Relative(_)));
+    }
+    if cfg.overlap {
+        return result;
+    }
+    let pinned = |addr: usize, op: &Opcode| (addr..addr + op.size()).any(|c| referenced[c]);
+    let reads_code = cfg.instrs.iter().any(|(&a, op)| pinned(a, op));
+    let relocatable = !cfg.indirect && !relative && !reads_code;
+
+    // In-place rewrites
+    let out = &mut result.program;
+    for (&addr, op) in &cfg.instrs {
+        if pinned(addr, op) {
+            continue;
+        }
+        match *op {
+            _ if faults(op) => {}
+            Opcode::Add(Mode::Immediate(a), Mode::Immediate(b), dst)
+            | Opcode::Mul(Mode::Immediate(a), Mode::Immediate(b), dst) => {
+                let value = match op {
+                    Opcode::Add(..) => a.wrapping_add(b),
+                    _ => a.wrapping_mul(b),
+                };
+                let code = if let Mode::Relative(_) = dst {
+                    21101
+                } else {
+                    1101
+                };
+                let folded = [code, value, 0];
+                if out[addr..addr + 3] != folded {
+                    out[addr..addr + 3].copy_from_slice(&folded);
+                    result.folded += 1;
+                }
+            }
+            Opcode::Jz(Mode::Immediate(0), target) => {
+                out[addr] = match target {
+                    Mode::Position(_) => 105,
+                    Mode::Immediate(_) => 1105,
+                    Mode::Relative(_) => 2105,
+                };
+                out[addr + 1] = 1;
+                result.jumps += 1;
+            }
+            _ => {}
+        }
+    }
+    if !relocatable {
+        return result;
+    }
+
+    // Decide which cells to keep. Faulting instructions are kept as they
+    // are, along with any operands that they might have
+    let mut keep = referenced;
+    for &addr in &cfg.faulting {
+        for k in keep.iter_mut().skip(addr).take(4) {
+            *k = true;
+        }
+    }
+    for (&addr, op) in &cfg.instrs {
+        let dead = match accesses(op).1 {
+            Some(dst) if !matches!(op, Opcode::Input(_)) => overwritten(&cfg, addr, dst),
+            _ => false,
+        };
+        let never_taken = taken(op) == Some(false);
+        result.dead_stores += dead as usize;
+        result.jumps += never_taken as usize;
+        if !dead && !never_taken {
+            for k in &mut keep[addr..addr + op.size()] {
+                *k = true;
+            }
+        }
+    }
+    result.unreachable = keep
+        .iter()
+        .zip(&cfg.owner)
+        .filter(|(&k, o)| !k && o.is_none())
+        .count();
+
+    // Compact, relocating jump targets and position operands
+    let mut moved = Vec::with_capacity(keep.len() + 1);
+    let mut removed = 0;
+    for &k in &keep {
+        moved.push(removed);
+        removed += !k as usize;
+    }
+    moved.push(removed);
+    let relocate = |addr: isize| -> isize {
+        if addr < 0 {
+            addr
+        } else {
+            addr - moved[(addr as usize).min(keep.len())] as isize
+        }
+    };
+
+    let rewritten = Vm::new(std::mem::take(&mut result.program));
+    let mut out = Vec::with_capacity(keep.len() - removed);
+    let mut addr = 0;
+    while addr < keep.len() {
+        let op = match cfg.instrs.get(&addr) {
+            Some(_) if keep[addr] => rewritten.decode(addr).ok(),
+            _ => None,
+        };
+        match op {
+            Some(op) => {
+                let jump = matches!(op, Opcode::Jnz(..) | Opcode::Jz(..));
+                out.push(rewritten.data[addr]);
+                for (n, p) in op.params().into_iter().enumerate() {
+                    out.push(match p {
+                        Mode::Position(a) => relocate(a as isize),
+                        Mode::Immediate(t) if jump && n == 1 => relocate(t),
+                        Mode::Immediate(x) | Mode::Relative(x) => x,
+                    });
+                }
+                addr += op.size();
+            }
+            None => {
+                if keep[addr] {
+                    out.push(rewritten.data[addr]);
+                }
+                addr += 1;
+            }
+        }
+    }
+    result.program = out;
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assemble, compile, Error};
+
+    fn outputs(program: Vec<isize>, input: &[isize]) -> (Vec<isize>, Error) {
+        let mut vm = Vm::new(program);
+        let mut input = input.iter().copied();
+        let mut out = Vec::new();
+        loop {
+            match vm.run(&mut input, false) {
+                Ok(x) => out.push(x),
+                Err(e) => return (out, e),
+            }
+        }
+    }
+
+    #[test]
+    fn folding_and_jumps() {
+        let program = assemble(
+            "       add #2, #3, x
+                    mul #4, #5, rel(7)
+                    jz #0, #next
+                    output #99
+             next:  output x
+                    halt
+             x:     data 0",
+        )
+        .unwrap();
+        let opt = optimize(&program);
+        assert_eq!((opt.folded, opt.jumps), (2, 1));
+        assert_eq!(
+            opt.program[..11],
+            [1101, 5, 0, 16, 21101, 20, 0, 7, 1105, 1, 13]
+        );
+        // The relative base is in use, so nothing moves
+        assert_eq!(opt.program.len(), program.len());
+        assert_eq!(outputs(opt.program, &[]), outputs(program, &[]));
+    }
+
+    #[test]
+    fn compaction() {
+        let program = assemble(
+            "       input x
+                    add x, #1, y        ; dead, y is overwritten below
+                    mul x, #2, y
+                    jnz #0, #skip       ; never taken
+                    jz #0, #done
+             skip:  output #-1          ; unreachable
+                    output #-2
+             done:  output y
+                    halt
+             x:     data 0
+             y:     data 0",
+        )
+        .unwrap();
+        let opt = optimize(&program);
+        assert_eq!(opt.dead_stores, 1);
+        assert_eq!(opt.jumps, 2);
+        assert_eq!(opt.unreachable, 4);
+        assert_eq!(
+            opt.program,
+            vec![3, 12, 1002, 12, 2, 13, 1105, 1, 9, 4, 13, 99, 0, 0]
+        );
+        for x in -3..3 {
+            assert_eq!(outputs(opt.program.clone(), &[x]).0, vec![x * 2]);
+        }
+    }
+
+    #[test]
+    fn self_modification() {
+        // Day 2 style: the result is written over the first instruction
+        let program = vec![1101, 2, 3, 0, 99];
+        let opt = optimize(&program);
+        assert!(opt.self_modifying);
+        assert_eq!(opt.program, program);
+
+        // The quine reads its own code, so nothing may move
+        let quine = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        let program = quine.parse::<Vm>().unwrap().data;
+        assert_eq!(optimize(&program).program, program);
+    }
+
+    #[test]
+    fn compiled_programs() {
+        let source = "
+            fn collatz(n) {
+                let steps = 0;
+                while n != 1 {
+                    if n % 2 == 0 { n = n / 2; } else { n = 3 * n + 1; }
+                    steps = steps + 1;
+                }
+                return steps;
+            }
+            let n = input();
+            output(collatz(n));
+            output(2 * 3 + collatz(6));";
+        let program = compile(source).unwrap();
+        let opt = optimize(&program);
+        assert!(!opt.self_modifying);
+        for n in 1..20 {
+            assert_eq!(
+                outputs(opt.program.clone(), &[n]),
+                outputs(program.clone(), &[n])
+            );
+        }
+    }
+}
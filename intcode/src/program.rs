@@ -0,0 +1,222 @@
+//! A text format for programs, with comments and a descriptive header.
+//!
+//! Values are separated by commas, with whitespace and newlines allowed
+//! anywhere and an optional trailing comma. A `#` starts a comment that runs
+//! to the end of the line. Comments at the top of the file, before the first
+//! value, may hold metadata as `key: value` pairs:
+//!
+//! ```text
+//! # name: echo
+//! # inputs: any number of values
+//! # note: loops forever, so stop it once it is starved
+//! 3,7,      # input
+//! 4,7,      # output
+//! 1105,1,0  # jump back to the start
+//! ```
+//!
+//! The keys are `name`, `inputs` and `note`, which may be repeated. Other
+//! comments are ignored. Plain comma separated puzzle inputs are valid
+//! programs without a header.
+use super::Vm;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    pub name: Option<String>,
+    /// Description of the input the program expects
+    pub inputs: Option<String>,
+    pub notes: Vec<String>,
+    pub data: Vec<isize>,
+}
+
+/// Where a program failed to parse, and the token found there
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// Line and column of the token, both starting from 1
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: unexpected '{}'",
+            self.line, self.column, self.token
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Program {
+    pub fn new(data: Vec<isize>) -> Program {
+        Program {
+            data,
+            ..Program::default()
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Program> {
+        std::fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl From<Program> for Vm {
+    fn from(program: Program) -> Vm {
+        Vm::new(program.data)
+    }
+}
+
+/// Split a line into tokens, each a comma or a run of anything else that
+/// isn't whitespace, along with the column it starts at
+fn tokens(line: &str) -> Vec<(usize, &str)> {
+    let mut out = Vec::new();
+    let mut start = None;
+    for (column, (i, c)) in line.char_indices().enumerate() {
+        if let Some((col, s)) = start {
+            if c == ',' || c.is_whitespace() {
+                out.push((col, &line[s..i]));
+                start = None;
+            }
+        }
+        if c == ',' {
+            out.push((column + 1, ","));
+        } else if !c.is_whitespace() && start.is_none() {
+            start = Some((column + 1, i));
+        }
+    }
+    if let Some((col, s)) = start {
+        out.push((col, &line[s..]));
+    }
+    out
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut program = Program::default();
+        // Whether a comma is needed before the next value
+        let mut separated = true;
+        for (n, line) in s.lines().enumerate() {
+            let (code, comment) = match line.find('#') {
+                Some(i) => (&line[..i], Some(line[i + 1..].trim())),
+                None => (line, None),
+            };
+            let header = program.data.is_empty() && code.trim().is_empty();
+            if let (true, Some((key, value))) = (header, comment.and_then(|c| c.split_once(':'))) {
+                let value = value.trim().to_string();
+                match key.trim() {
+                    "name" => program.name = Some(value),
+                    "inputs" => program.inputs = Some(value),
+                    "note" => program.notes.push(value),
+                    _ => {}
+                }
+            }
+
+            for (column, token) in tokens(code) {
+                let error = || ParseError {
+                    line: n + 1,
+                    column,
+                    token: token.to_string(),
+                };
+                match (token, separated) {
+                    (",", false) => separated = true,
+                    (_, true) if token != "," => {
+                        program.data.push(token.parse().map_err(|_| error())?);
+                        separated = false;
+                    }
+                    _ => return Err(error()),
+                }
+            }
+        }
+        Ok(program)
+    }
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(name) = &self.name {
+            writeln!(f, "# name: {}", name)?;
+        }
+        if let Some(inputs) = &self.inputs {
+            writeln!(f, "# inputs: {}", inputs)?;
+        }
+        for note in &self.notes {
+            writeln!(f, "# note: {}", note)?;
+        }
+        let values = self.data.iter().map(isize::to_string).collect::<Vec<_>>();
+        writeln!(f, "{}", values.join(","))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn comments_and_header() {
+        let source = "
+            # name: echo
+            # inputs: any number of values
+            #
+            # note: loops forever
+            # note: stop it once it is starved
+            3,7,      # input
+            4,7,      # output: not metadata
+
+            1105 , 1 ,
+            0,        # jump back to the start
+            ";
+        let program = source.parse::<Program>().unwrap();
+        assert_eq!(program.name.as_deref(), Some("echo"));
+        assert_eq!(program.inputs.as_deref(), Some("any number of values"));
+        assert_eq!(
+            program.notes,
+            vec!["loops forever", "stop it once it is starved"]
+        );
+        assert_eq!(program.data, vec![3, 7, 4, 7, 1105, 1, 0]);
+
+        let mut vm = Vm::from(program);
+        assert_eq!(vm.run(std::iter::once(5), false), Ok(5));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut program = Program::new(vec![1, 0, -3, 99]);
+        assert_eq!(program.to_string(), "1,0,-3,99\n");
+        assert_eq!(program.to_string().parse(), Ok(program.clone()));
+
+        program.name = Some("test: with a colon".to_string());
+        program.notes = vec!["one".to_string(), "two".to_string()];
+        assert_eq!(program.to_string().parse(), Ok(program.clone()));
+
+        let path = std::env::temp_dir().join(format!("intcode-program-{}", std::process::id()));
+        program.save(&path).unwrap();
+        assert_eq!(Program::load(&path).unwrap(), program);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        let err = |source: &str| source.parse::<Program>().unwrap_err().to_string();
+        assert_eq!(err("1,2,x"), "line 1, column 5: unexpected 'x'");
+        assert_eq!(
+            err("# name: a\n1,\n  2 3"),
+            "line 3, column 5: unexpected '3'"
+        );
+        assert_eq!(err("1,,2"), "line 1, column 3: unexpected ','");
+        assert_eq!(err(",1"), "line 1, column 1: unexpected ','");
+        assert_eq!(err("1,2\n3"), "line 2, column 1: unexpected '3'");
+        assert_eq!(err("1,\u{e9}9"), "line 1, column 3: unexpected '\u{e9}9'");
+        assert_eq!("1,\n2".parse::<Vm>().map(|vm| vm.data), Ok(vec![1, 2]));
+    }
+}
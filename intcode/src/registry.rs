@@ -0,0 +1,298 @@
+//! User-registered instructions that extend the base intcode instruction set.
+//!
+//! Custom instructions use two-digit opcodes (10 through 98) and are decoded
+//! with the same parameter mode digits as the built-in instructions. Once a
+//! [`Registry`] is attached to a [`Vm`] with [`Vm::set_registry`], the decoder,
+//! disassembler and tracer all pick the new instructions up.
+//!
+//! ```
+//! use intcode::{Param, Registry, Vm};
+//! use std::sync::Arc;
+//!
+//! let mut registry = Registry::new();
+//! registry.register("div", 10, &[Param::Read, Param::Read, Param::Write], |vm, p| {
+//!     let a = vm.fetch(p[0])?;
+//!     let b = vm.fetch(p[1])?;
+//!     vm.store_or_extend(p[2], a / b)?;
+//!     Ok(None)
+//! });
+//!
+//! let mut vm = "1110,84,4,7,4,7,99,0".parse::<Vm>().unwrap();
+//! vm.set_registry(Arc::new(registry));
+//! assert_eq!(vm.run(std::iter::empty(), false), Ok(21));
+//! ```
+use super::{Error, Mode, Vm, MAX_PARAMS};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// How an instruction uses one of its parameters
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Param {
+    /// The parameter is a value, and may be given in any mode
+    Read,
+    /// The parameter is a destination address, and must not be immediate
+    Write,
+}
+
+/// Semantics of a custom instruction. The handler receives the decoded
+/// parameters and is free to read or write memory through [`Vm::fetch`] and
+/// [`Vm::store_or_extend`], or to move `ip` and `base`. Returning `Some` value
+/// emits it as an output of the machine
+pub type Handler = dyn Fn(&mut Vm, &[Mode]) -> Result<Option<isize>, Error> + Send + Sync;
+
+#[derive(Clone)]
+pub struct Instruction {
+    pub name: &'static str,
+    pub code: isize,
+    pub params: Vec<Param>,
+    pub(crate) handler: Arc<Handler>,
+}
+
+impl Instruction {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+}
+
+impl std::fmt::Debug for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Instruction")
+            .field("name", &self.name)
+            .field("code", &self.code)
+            .field("params", &self.params)
+            .finish()
+    }
+}
+
+/// Handlers can't be compared, so two instructions are considered equal if
+/// they have the same signature
+impl PartialEq for Instruction {
+    fn eq(&self, other: &Instruction) -> bool {
+        self.name == other.name && self.code == other.code && self.params == other.params
+    }
+}
+
+/// A set of custom instructions, keyed by opcode
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Registry {
+    instrs: BTreeMap<isize, Instruction>,
+}
+
+/// Mnemonics used by the built-in instruction set
+pub const BUILTIN_NAMES: [&str; 10] = [
+    "add", "mul", "input", "output", "jnz", "jz", "lt", "eq", "offset", "halt",
+];
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Add a custom instruction.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `code` is not a two-digit opcode between 10 and 98, if either
+    /// the opcode or the name is already taken, or if the instruction has more
+    /// than [`MAX_PARAMS`] parameters
+    pub fn register<F>(&mut self, name: &'static str, code: isize, params: &[Param], handler: F)
+    where
+        F: Fn(&mut Vm, &[Mode]) -> Result<Option<isize>, Error> + Send + Sync + 'static,
+    {
+        assert!(
+            (10..=98).contains(&code),
+            "custom opcode {} must be between 10 and 98",
+            code
+        );
+        assert!(
+            params.len() <= MAX_PARAMS,
+            "{} has more than {} parameters",
+            name,
+            MAX_PARAMS
+        );
+        assert!(
+            !BUILTIN_NAMES.contains(&name) && self.by_name(name).is_none(),
+            "instruction {} is already defined",
+            name
+        );
+        assert!(
+            !self.instrs.contains_key(&code),
+            "opcode {} is already registered",
+            code
+        );
+        self.instrs.insert(
+            code,
+            Instruction {
+                name,
+                code,
+                params: params.to_vec(),
+                handler: Arc::new(handler),
+            },
+        );
+    }
+
+    pub fn get(&self, code: isize) -> Option<&Instruction> {
+        self.instrs.get(&code)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&Instruction> {
+        self.instrs.values().find(|i| i.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Instruction> {
+        self.instrs.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instrs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Opcode, Step};
+    use std::sync::Mutex;
+
+    fn binary(
+        registry: &mut Registry,
+        name: &'static str,
+        code: isize,
+        f: fn(isize, isize) -> isize,
+    ) {
+        registry.register(
+            name,
+            code,
+            &[Param::Read, Param::Read, Param::Write],
+            move |vm, p| {
+                let a = vm.fetch(p[0])?;
+                let b = vm.fetch(p[1])?;
+                vm.store_or_extend(p[2], f(a, b))?;
+                Ok(None)
+            },
+        );
+    }
+
+    fn teaching() -> Registry {
+        let mut registry = Registry::new();
+        binary(&mut registry, "div", 10, |a, b| a / b);
+        binary(&mut registry, "mod", 11, |a, b| a % b);
+        binary(&mut registry, "and", 12, |a, b| a & b);
+        binary(&mut registry, "or", 13, |a, b| a | b);
+        binary(&mut registry, "xor", 14, |a, b| a ^ b);
+        registry
+    }
+
+    fn vm(program: &str, registry: Registry) -> Vm {
+        let mut vm = program.parse::<Vm>().unwrap();
+        vm.set_registry(Arc::new(registry));
+        vm
+    }
+
+    #[test]
+    fn arithmetic() {
+        // 17 mod 5, 12 & 10, 12 ^ 10
+        let mut vm = vm(
+            "1111,17,5,0,4,0,1112,12,10,0,4,0,1114,12,10,0,4,0,99",
+            teaching(),
+        );
+        assert_eq!(vm.run(std::iter::empty(), false), Ok(2));
+        assert_eq!(vm.run(std::iter::empty(), false), Ok(8));
+        assert_eq!(vm.run(std::iter::empty(), false), Ok(6));
+        assert_eq!(vm.run(std::iter::empty(), false), Err(Error::Halted));
+    }
+
+    #[test]
+    fn two_digit_decoding() {
+        // Without a registry, 11 is decoded as an add
+        let program = "1111,17,5,0,99";
+        let plain = program.parse::<Vm>().unwrap();
+        assert_eq!(
+            plain.decode(0),
+            Ok(Opcode::Add(
+                Mode::Immediate(17),
+                Mode::Immediate(5),
+                Mode::Position(0)
+            ))
+        );
+        let custom = vm(program, teaching());
+        let op = custom.decode(0).unwrap();
+        assert_eq!(op.name(), "mod");
+        assert_eq!(
+            op.params(),
+            vec![Mode::Immediate(17), Mode::Immediate(5), Mode::Position(0)]
+        );
+        assert_eq!(op.to_string(), "mod #17, #5, 0");
+        assert_eq!(
+            custom
+                .disassemble()
+                .iter()
+                .map(|(ip, op)| format!("{}: {}", ip, op))
+                .collect::<Vec<_>>(),
+            vec!["0: mod #17, #5, 0", "4: halt"]
+        );
+    }
+
+    #[test]
+    fn syscall() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = log.clone();
+        let mut registry = Registry::new();
+        // syscall n, a: 1 = log a, 2 = echo a as an output
+        registry.register("syscall", 20, &[Param::Read, Param::Read], move |vm, p| {
+            let n = vm.fetch(p[0])?;
+            let a = vm.fetch(p[1])?;
+            match n {
+                1 => {
+                    sink.lock().unwrap().push(a);
+                    Ok(None)
+                }
+                2 => Ok(Some(a)),
+                _ => Err(Error::InvalidData),
+            }
+        });
+
+        let mut vm = vm("1120,1,42,1120,2,7,1120,3,0,99", registry);
+        assert_eq!(vm.step(|| None, false), Ok(Step::Continue));
+        assert_eq!(*log.lock().unwrap(), vec![42]);
+        assert_eq!(vm.run(std::iter::empty(), false), Ok(7));
+        assert_eq!(vm.run(std::iter::empty(), false), Err(Error::InvalidData));
+    }
+
+    #[test]
+    fn immediate_destination() {
+        // An immediate write parameter is rejected where it is decoded
+        let mut div = vm("11110,84,4,7,99", teaching());
+        assert_eq!(div.decode(0), Err(Error::InvalidMode(3, 1)));
+        assert_eq!(
+            div.run(std::iter::empty(), false),
+            Err(Error::InvalidMode(3, 1))
+        );
+
+        // A handler that jumps back to 0 and then writes through a read
+        // parameter is reported at its own address
+        let mut registry = Registry::new();
+        registry.register("reset", 20, &[Param::Read], |vm, p| {
+            vm.ip = 0;
+            vm.store_or_extend(p[0], 1)?;
+            Ok(None)
+        });
+        let mut reset = vm("99,120,7", registry);
+        reset.ip = 1;
+        assert_eq!(reset.step(|| None, false), Err(Error::InvalidMode(1, 1)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn reserved_opcode() {
+        let mut registry = Registry::new();
+        binary(&mut registry, "div", 99, |a, b| a / b);
+    }
+
+    #[test]
+    #[should_panic]
+    fn duplicate_name() {
+        let mut registry = teaching();
+        binary(&mut registry, "div", 30, |a, b| a / b);
+    }
+}
@@ -0,0 +1,256 @@
+//! Cooperative scheduling of many machines that talk to each other.
+//!
+//! Each machine has a queue of pending input. Machines take turns running for
+//! a time slice of instructions, and a machine that runs out of input simply
+//! gives up the rest of its turn until something is sent to it. Outputs go to
+//! the queue of the machine they are connected to, or are collected for the
+//! caller if the machine isn't connected to anything.
+//!
+//! ```
+//! use intcode::{Scheduler, Vm};
+//!
+//! // Adds one to each input, forever
+//! let inc = "3,11,1001,11,1,11,4,11,1105,1,0,0".parse::<Vm>().unwrap();
+//! let mut scheduler = Scheduler::new(100);
+//! let a = scheduler.add(inc.clone());
+//! let b = scheduler.add(inc);
+//! scheduler.connect(a, b);
+//! scheduler.send(a, 40);
+//!
+//! // Both machines end up waiting for input that will never come
+//! let deadlock = scheduler.run().unwrap_err();
+//! assert_eq!(deadlock.to_string(), "deadlock: 0 is waiting on nothing, 1 on 0");
+//! assert_eq!(scheduler.take_outputs(b), vec![42]);
+//! ```
+use super::{Error, Step, Vm};
+use std::collections::VecDeque;
+
+struct Machine {
+    vm: Vm,
+    input: VecDeque<isize>,
+    /// Machine that receives the outputs, if any
+    target: Option<usize>,
+    outputs: Vec<isize>,
+    halted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScheduleError {
+    /// Every machine that hasn't halted is waiting for input. Lists each of
+    /// them along with the machines connected to it
+    Deadlock(Vec<(usize, Vec<usize>)>),
+    /// A machine stopped with an error other than halting
+    Fault(usize, Error),
+}
+
+impl std::fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ScheduleError::Deadlock(waiting) => {
+                write!(f, "deadlock: ")?;
+                for (i, (id, sources)) in waiting.iter().enumerate() {
+                    let sources = sources
+                        .iter()
+                        .map(usize::to_string)
+                        .collect::<Vec<_>>()
+                        .join(" and ");
+                    let sources = if sources.is_empty() {
+                        "nothing"
+                    } else {
+                        &sources
+                    };
+                    if i == 0 {
+                        write!(f, "{} is waiting on {}", id, sources)?;
+                    } else {
+                        write!(f, ", {} on {}", id, sources)?;
+                    }
+                }
+                Ok(())
+            }
+            ScheduleError::Fault(id, e) => write!(f, "machine {} failed: {:?}", id, e),
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+pub struct Scheduler {
+    machines: Vec<Machine>,
+    /// Instructions each machine may execute before the next one gets a turn
+    slice: usize,
+}
+
+impl Scheduler {
+    pub fn new(slice: usize) -> Scheduler {
+        Scheduler {
+            machines: Vec::new(),
+            slice: slice.max(1),
+        }
+    }
+
+    /// Add a machine, returning the id used to refer to it
+    pub fn add(&mut self, vm: Vm) -> usize {
+        self.machines.push(Machine {
+            vm,
+            input: VecDeque::new(),
+            target: None,
+            outputs: Vec::new(),
+            halted: false,
+        });
+        self.machines.len() - 1
+    }
+
+    /// Send the outputs of `from` to the input of `to`
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.machines[from].target = Some(to);
+    }
+
+    /// Queue an input value for a machine
+    pub fn send(&mut self, to: usize, value: isize) {
+        self.machines[to].input.push_back(value);
+    }
+
+    pub fn vm(&self, id: usize) -> &Vm {
+        &self.machines[id].vm
+    }
+
+    pub fn is_halted(&self, id: usize) -> bool {
+        self.machines[id].halted
+    }
+
+    /// Input queued for a machine that it hasn't consumed yet
+    pub fn pending(&self, id: usize) -> &VecDeque<isize> {
+        &self.machines[id].input
+    }
+
+    /// Remove the outputs collected from a machine that isn't connected
+    pub fn take_outputs(&mut self, id: usize) -> Vec<isize> {
+        std::mem::take(&mut self.machines[id].outputs)
+    }
+
+    /// Give every running machine one time slice, returning whether any of
+    /// them executed an instruction
+    pub fn round(&mut self) -> Result<bool, ScheduleError> {
+        let mut progress = false;
+        for id in 0..self.machines.len() {
+            let mut routed = Vec::new();
+            let machine = &mut self.machines[id];
+            if machine.halted {
+                continue;
+            }
+            for _ in 0..self.slice {
+                let input = &mut machine.input;
+                match machine.vm.step(|| input.pop_front(), false) {
+                    Ok(step) => {
+                        progress = true;
+                        if let Step::Output(x) = step {
+                            match machine.target {
+                                Some(to) => routed.push((to, x)),
+                                None => machine.outputs.push(x),
+                            }
+                        }
+                    }
+                    Err(Error::NoInput) => break,
+                    Err(Error::Halted) => {
+                        progress = true;
+                        machine.halted = true;
+                        break;
+                    }
+                    Err(e) => return Err(ScheduleError::Fault(id, e)),
+                }
+            }
+            for (to, x) in routed {
+                self.send(to, x);
+            }
+        }
+        Ok(progress)
+    }
+
+    /// Run until every machine has halted
+    pub fn run(&mut self) -> Result<(), ScheduleError> {
+        while self.machines.iter().any(|m| !m.halted) {
+            if !self.round()? {
+                return Err(ScheduleError::Deadlock(self.waiting()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Machines that are still running, each with those connected to it
+    fn waiting(&self) -> Vec<(usize, Vec<usize>)> {
+        let sources = |id| {
+            let m = self.machines.iter().enumerate();
+            m.filter(|(_, m)| m.target == Some(id))
+                .map(|(i, _)| i)
+                .collect()
+        };
+        (0..self.machines.len())
+            .filter(|&id| !self.machines[id].halted)
+            .map(|id| (id, sources(id)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn feedback_loop() {
+        let vm = "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,
+                  27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5"
+            .parse::<Vm>()
+            .unwrap();
+        for &slice in &[1, 7, 1000] {
+            let mut scheduler = Scheduler::new(slice);
+            for phase in &[9, 8, 7, 6, 5] {
+                let id = scheduler.add(vm.clone());
+                scheduler.send(id, *phase);
+                scheduler.connect(id, (id + 1) % 5);
+            }
+            scheduler.send(0, 0);
+            assert_eq!(scheduler.run(), Ok(()));
+            assert_eq!(scheduler.pending(0), &[139629729]);
+            assert!((0..5).all(|id| scheduler.is_halted(id)));
+        }
+    }
+
+    #[test]
+    fn deadlock() {
+        // Reads two values and outputs their sum
+        let add = "3,11,3,12,1,11,12,13,4,13,99,0,0,0".parse::<Vm>().unwrap();
+        let mut scheduler = Scheduler::new(3);
+        let a = scheduler.add(add.clone());
+        let b = scheduler.add(add.clone());
+        let c = scheduler.add(add);
+        scheduler.connect(a, b);
+        scheduler.connect(b, a);
+        scheduler.send(a, 1);
+        scheduler.send(c, 2);
+        scheduler.send(c, 3);
+        assert_eq!(
+            scheduler.run(),
+            Err(ScheduleError::Deadlock(vec![(0, vec![1]), (1, vec![0])]))
+        );
+        assert_eq!(scheduler.take_outputs(c), vec![5]);
+        assert!(scheduler.is_halted(c));
+
+        // Machines resume where they left off once input arrives
+        scheduler.send(a, 2);
+        scheduler.send(b, 4);
+        assert_eq!(scheduler.run(), Ok(()));
+        assert_eq!(scheduler.pending(a), &[7]);
+    }
+
+    #[test]
+    fn fault() {
+        let mut scheduler = Scheduler::new(10);
+        scheduler.add("99".parse().unwrap());
+        scheduler.add("1105,1,0".parse().unwrap());
+        scheduler.add("204,-1".parse().unwrap());
+        assert_eq!(
+            scheduler.run(),
+            Err(ScheduleError::Fault(2, Error::NegativeAddr(-1)))
+        );
+    }
+}
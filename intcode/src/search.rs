@@ -0,0 +1,273 @@
+//! Brute-force searches over a machine's inputs, in parallel.
+//!
+//! A [`Search`] runs a copy of a machine for every [`Candidate`], a set of
+//! memory patches and input values, on a pool of threads. Each run is
+//! summed up as an [`Outcome`] with the machine's final state and outputs,
+//! and a predicate picks out the matches. [`Search::first`] finds the
+//! earliest matching candidate in the order they were generated, and
+//! cancels work on any later candidates as soon as one matches.
+//!
+//! ```
+//! use intcode::{Candidate, Search, Vm};
+//!
+//! // As in day 2, find the noun and verb that leave 19690000 at address 0
+//! let vm = "1,0,0,0,2,0,14,0,99,0,0,0,19690,0,1000".parse::<Vm>().unwrap();
+//! let candidates = (0..15).flat_map(|noun| {
+//!     (0..15).map(move |verb| Candidate::new().patch(1, noun).patch(2, verb))
+//! });
+//! let found = Search::new(vm)
+//!     .first(candidates, |outcome| outcome.vm.data[0] == 19690000)
+//!     .unwrap();
+//! assert_eq!(found.candidate.patches, vec![(1, 3), (2, 12)]);
+//! ```
+use super::{Error, Step, Vm};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Instructions executed between checks for whether a run was cancelled
+const CHECK: u64 = 1000;
+
+/// Changes to make to the machine before running it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Candidate {
+    pub patches: Vec<(usize, isize)>,
+    pub input: Vec<isize>,
+}
+
+impl Candidate {
+    pub fn new() -> Candidate {
+        Candidate::default()
+    }
+
+    /// Set the memory cell `addr` to `value`
+    pub fn patch(mut self, addr: usize, value: isize) -> Candidate {
+        self.patches.push((addr, value));
+        self
+    }
+
+    /// Feed `values` to the machine, after any input already added
+    pub fn input(mut self, values: &[isize]) -> Candidate {
+        self.input.extend_from_slice(values);
+        self
+    }
+}
+
+/// How a machine ended up after running with one candidate
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub candidate: Candidate,
+    /// Position of the candidate in the order they were generated
+    pub index: usize,
+    /// Final state of the machine
+    pub vm: Vm,
+    pub outputs: Vec<isize>,
+    /// The error that stopped the machine, which is `Halted` if it finished,
+    /// `InvalidAddr` if a patch was out of range and it never ran, or `None`
+    /// if it reached the instruction limit
+    pub stop: Option<Error>,
+}
+
+/// A search over the candidates for a machine
+#[derive(Clone, Debug)]
+pub struct Search {
+    vm: Vm,
+    threads: usize,
+    limit: Option<u64>,
+}
+
+impl Search {
+    /// Search with one thread per CPU, and no limit on instructions
+    pub fn new(vm: Vm) -> Search {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Search {
+            vm,
+            threads,
+            limit: None,
+        }
+    }
+
+    pub fn threads(mut self, threads: usize) -> Search {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Stop each run after `steps` instructions, so that candidates that
+    /// send the machine into an infinite loop don't hold up the search
+    pub fn limit(mut self, steps: u64) -> Search {
+        self.limit = Some(steps);
+        self
+    }
+
+    /// The earliest candidate whose outcome satisfies `matches`
+    pub fn first<I, P>(&self, candidates: I, matches: P) -> Option<Outcome>
+    where
+        I: IntoIterator<Item = Candidate>,
+        I::IntoIter: Send,
+        P: Fn(&Outcome) -> bool + Sync,
+    {
+        self.search(candidates, matches, true).into_iter().next()
+    }
+
+    /// Every candidate whose outcome satisfies `matches`, in order
+    pub fn all<I, P>(&self, candidates: I, matches: P) -> Vec<Outcome>
+    where
+        I: IntoIterator<Item = Candidate>,
+        I::IntoIter: Send,
+        P: Fn(&Outcome) -> bool + Sync,
+    {
+        self.search(candidates, matches, false)
+    }
+
+    fn search<I, P>(&self, candidates: I, matches: P, first: bool) -> Vec<Outcome>
+    where
+        I: IntoIterator<Item = Candidate>,
+        I::IntoIter: Send,
+        P: Fn(&Outcome) -> bool + Sync,
+    {
+        let queue = Mutex::new(candidates.into_iter().enumerate());
+        // Index of the earliest match so far, when only the first is wanted
+        let best = AtomicUsize::new(usize::MAX);
+        let found = Mutex::new(Vec::new());
+
+        std::thread::scope(|scope| {
+            for _ in 0..self.threads {
+                let (queue, best, found, matches) = (&queue, &best, &found, &matches);
+                scope.spawn(move || loop {
+                    let next = queue.lock().unwrap().next();
+                    let (index, candidate) = match next {
+                        Some(next) => next,
+                        None => return,
+                    };
+                    let cancel = if first { Some(best) } else { None };
+                    if cancel.is_some_and(|best| best.load(Ordering::Relaxed) < index) {
+                        return;
+                    }
+                    if let Some(outcome) = self.run(self.vm.clone(), index, candidate, cancel) {
+                        if matches(&outcome) {
+                            best.fetch_min(index, Ordering::Relaxed);
+                            found.lock().unwrap().push(outcome);
+                        }
+                    }
+                });
+            }
+        });
+
+        let mut found = found.into_inner().unwrap();
+        found.sort_by_key(|outcome| outcome.index);
+        if first {
+            found.truncate(1);
+        }
+        found
+    }
+
+    /// Run `vm` with `candidate`, giving up if an earlier candidate than
+    /// this one is found to match
+    fn run(
+        &self,
+        mut vm: Vm,
+        index: usize,
+        candidate: Candidate,
+        cancel: Option<&AtomicUsize>,
+    ) -> Option<Outcome> {
+        let patched = candidate
+            .patches
+            .iter()
+            .try_for_each(|&(addr, value)| vm.patch(addr, value));
+        let mut input = candidate.input.iter().copied();
+        let mut outputs = Vec::new();
+        let mut steps = 0;
+        // A candidate whose patches don't fit is reported without running
+        let stop = match patched {
+            Err(e) => Some(e),
+            Ok(()) => loop {
+                if self.limit.is_some_and(|limit| steps >= limit) {
+                    break None;
+                }
+                if steps % CHECK == 0
+                    && cancel.is_some_and(|best| best.load(Ordering::Relaxed) < index)
+                {
+                    return None;
+                }
+                steps += 1;
+                match vm.step(|| input.next(), false) {
+                    Ok(Step::Output(x)) => outputs.push(x),
+                    Ok(Step::Continue) => {}
+                    Err(e) => break Some(e),
+                }
+            },
+        };
+        Some(Outcome {
+            candidate,
+            index,
+            vm,
+            outputs,
+            stop,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MAX_MEMORY;
+
+    #[test]
+    fn first_in_order() {
+        // Outputs the square of its input
+        let square = "3,9,2,9,9,9,4,9,99,0".parse::<Vm>().unwrap();
+        let candidates = (0..1000).map(|x| Candidate::new().input(&[x]));
+        for threads in 1..4 {
+            let search = Search::new(square.clone()).threads(threads);
+            let found = search
+                .first(candidates.clone(), |o| o.outputs[0] > 500)
+                .unwrap();
+            assert_eq!(found.index, 23);
+            assert_eq!(found.outputs, vec![529]);
+            assert_eq!(found.stop, Some(Error::Halted));
+        }
+        let all = Search::new(square).all(candidates, |o| o.outputs[0] % 100 == 0);
+        let roots = all.iter().map(|o| o.candidate.input[0]).collect::<Vec<_>>();
+        assert_eq!(roots, (0..1000).step_by(10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn limits_and_failures() {
+        // Loops forever if the input is zero, and needs a second input
+        // otherwise
+        let vm = "3,11,1006,11,2,3,12,4,12,99,0,0,0".parse::<Vm>().unwrap();
+        let search = Search::new(vm).limit(1000).threads(2);
+        let candidates = vec![
+            Candidate::new().input(&[0]),
+            Candidate::new().input(&[1]),
+            Candidate::new().input(&[1, 5]),
+        ];
+        let stops = search
+            .all(candidates, |_| true)
+            .into_iter()
+            .map(|o| (o.stop, o.outputs))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stops,
+            vec![
+                (None, vec![]),
+                (Some(Error::NoInput), vec![]),
+                (Some(Error::Halted), vec![5]),
+            ]
+        );
+    }
+
+    #[test]
+    fn patch_out_of_range() {
+        let vm = "1,0,0,0,99".parse::<Vm>().unwrap();
+        let candidates = vec![
+            Candidate::new().patch(MAX_MEMORY, 1),
+            Candidate::new().patch(1, 4),
+        ];
+        let outcomes = Search::new(vm).all(candidates, |_| true);
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0].stop, Some(Error::InvalidAddr(MAX_MEMORY)));
+        assert_eq!(outcomes[0].vm.ip, 0);
+        assert_eq!(outcomes[1].stop, Some(Error::Halted));
+        assert_eq!(outcomes[1].vm.data[0], 100);
+    }
+}
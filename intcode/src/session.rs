@@ -0,0 +1,184 @@
+//! Driving a machine over a byte stream, such as a network connection.
+//!
+//! [`session`] runs a machine until it stops, reading its input from one
+//! stream and writing its output to another. Input is only read when the
+//! machine asks for it, and pending output is flushed first, so an
+//! interactive program's prompt reaches the other end before it blocks. The
+//! `intcode-server` binary hosts a session for every connection to a socket.
+use super::{Error, Step, Vm};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+
+/// How values are encoded on the stream
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// Input is read a line at a time, each byte becoming a value. Outputs
+    /// below 128 are written as characters, and anything else as a number
+    /// on its own line
+    Ascii,
+    /// Input values are separated by newlines, commas or spaces, and each
+    /// output is written on its own line
+    Numbers,
+}
+
+impl Framing {
+    /// Read a line's worth of input into `pending`, leaving it empty at the
+    /// end of the stream
+    fn read<R: BufRead, W: Write>(
+        self,
+        input: &mut R,
+        output: &mut W,
+        pending: &mut VecDeque<isize>,
+    ) -> io::Result<()> {
+        let mut line = String::new();
+        while pending.is_empty() {
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            if self == Framing::Ascii {
+                pending.extend(line.bytes().map(isize::from));
+                continue;
+            }
+            for token in line.split(|c: char| c == ',' || c.is_whitespace()) {
+                match token.parse() {
+                    Ok(x) => pending.push_back(x),
+                    // Let the other end know, rather than dropping it
+                    Err(_) if !token.is_empty() => {
+                        writeln!(output, "invalid number '{}'", token)?;
+                        output.flush()?;
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write<W: Write>(self, output: &mut W, x: isize) -> io::Result<()> {
+        match self {
+            Framing::Ascii if (0..128).contains(&x) => output.write_all(&[x as u8]),
+            _ => writeln!(output, "{}", x),
+        }
+    }
+}
+
+/// Run `vm` until it stops, returning the error that stopped it: `Halted`
+/// if it finished, or `NoInput` if `input` was closed while the machine
+/// was waiting for more. With a `limit`, the machine is stopped after that
+/// many instructions and `None` is returned, so that a program that never
+/// touches the stream can't run forever
+pub fn session<R: BufRead, W: Write>(
+    mut vm: Vm,
+    mut input: R,
+    mut output: W,
+    framing: Framing,
+    limit: Option<u64>,
+) -> io::Result<Option<Error>> {
+    let mut pending = VecDeque::new();
+    let mut failed = None;
+    let mut steps = 0;
+    loop {
+        if limit.is_some_and(|limit| steps >= limit) {
+            output.flush()?;
+            return Ok(None);
+        }
+        steps += 1;
+        let step = vm.step(
+            || {
+                if pending.is_empty() {
+                    let read = output
+                        .flush()
+                        .and_then(|_| framing.read(&mut input, &mut output, &mut pending));
+                    failed = read.err();
+                }
+                pending.pop_front()
+            },
+            false,
+        );
+        if let Some(e) = failed {
+            return Err(e);
+        }
+        match step {
+            Ok(Step::Output(x)) => framing.write(&mut output, x)?,
+            Ok(Step::Continue) => {}
+            Err(e) => {
+                output.flush()?;
+                return Ok(Some(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble;
+
+    fn run(source: &str, input: &str, framing: Framing) -> (Error, String) {
+        let (stop, output) = run_limited(source, input, framing, None);
+        (stop.unwrap(), output)
+    }
+
+    fn run_limited(
+        source: &str,
+        input: &str,
+        framing: Framing,
+        limit: Option<u64>,
+    ) -> (Option<Error>, String) {
+        let mut output = Vec::new();
+        let vm = Vm::new(assemble(source).unwrap());
+        let stop = session(vm, input.as_bytes(), &mut output, framing, limit).unwrap();
+        (stop, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn numbers() {
+        let sum = "
+            loop:   input x
+                    jz x, #done
+                    add x, total, total
+                    jmp loop
+            done:   output total
+                    halt
+            x:      data 0
+            total:  data 0";
+        let halted = (Error::Halted, "10\n".to_string());
+        assert_eq!(run(sum, "1\n2, 3\n\n4 0\n", Framing::Numbers), halted);
+        let invalid = (Error::NoInput, "invalid number 'x'\n".to_string());
+        assert_eq!(run(sum, "1\nx 2\n", Framing::Numbers), invalid);
+    }
+
+    #[test]
+    fn ascii() {
+        let upper = "
+            loop:   input c
+                    lt c, #97, t        ; 'a'
+                    jnz t, #out
+                    lt c, #123, t       ; 'z' + 1
+                    jz t, #out
+                    add c, #-32, c
+            out:    output c
+                    jmp loop
+            c:      data 0
+            t:      data 0";
+        let echoed = (Error::NoInput, "NORTH\nHI!\n".to_string());
+        assert_eq!(run(upper, "north\nHi!\n", Framing::Ascii), echoed);
+        let large = (Error::Halted, "200\n\n".to_string());
+        assert_eq!(
+            run("output #200\noutput #10\nhalt", "", Framing::Ascii),
+            large
+        );
+    }
+
+    #[test]
+    fn limit() {
+        // Never reads or writes after its first output, so only the limit
+        // stops it
+        let spin = "output #1\nloop: jmp loop";
+        let stopped = (None, "1\n".to_string());
+        assert_eq!(run_limited(spin, "", Framing::Numbers, Some(100)), stopped);
+        let halted = (Some(Error::Halted), "".to_string());
+        assert_eq!(run_limited("halt", "", Framing::Numbers, Some(1)), halted);
+    }
+}
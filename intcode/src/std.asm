@@ -0,0 +1,93 @@
+; Standard library for the intcode assembler, pulled in with `include std`.
+;
+; Every routine follows the calling convention described in the `asm` module:
+; arguments are pushed in order before `call`, so that the last argument is at
+; rel(-2) on entry, and the result is returned with `ret value`. Routines that
+; don't call anything use the cells above the stack, rel(0) onwards, as
+; scratch space.
+
+    export std_div, std_mod, std_dot, std_print_num, std_print_str
+
+; std_div(a, b): a / b for a >= 0 and b > 0, by repeated subtraction
+std_div:
+    add #0, #0, rel(0)              ; quotient
+    add rel(-3), #0, rel(1)         ; remainder
+std_div_loop:
+    lt rel(1), rel(-2), rel(2)
+    jnz rel(2), #std_div_done
+    mul rel(-2), #-1, rel(2)
+    add rel(1), rel(2), rel(1)
+    add rel(0), #1, rel(0)
+    jmp std_div_loop
+std_div_done:
+    ret rel(0)
+
+; std_mod(a, b): a % b for a >= 0 and b > 0
+std_mod:
+    push rel(-3)
+    push rel(-3)
+    call std_div
+    mul rel(1), rel(-1), rel(1)     ; b * (a / b)
+    drop 2
+    mul rel(3), #-1, rel(3)
+    add rel(-3), rel(3), rel(0)
+    ret rel(0)
+
+; std_dot(a, b, n): sum of a[i] * b[i] over the n cells starting at addresses
+; a and b. The multiply-accumulate instruction has its operands patched on
+; every iteration
+std_dot:
+    add #0, #0, rel(0)              ; accumulator
+std_dot_loop:
+    jz rel(-2), #std_dot_done
+    add rel(-4), #0, std_dot_mul+1
+    add rel(-3), #0, std_dot_mul+2
+std_dot_mul:
+    mul 0, 0, rel(1)
+    add rel(0), rel(1), rel(0)
+    add rel(-4), #1, rel(-4)
+    add rel(-3), #1, rel(-3)
+    add rel(-2), #-1, rel(-2)
+    jmp std_dot_loop
+std_dot_done:
+    ret rel(0)
+
+; std_print_num(n): output n as ASCII decimal digits
+std_print_num:
+    lt rel(-2), #0, rel(0)
+    jz rel(0), #std_print_num_pos
+    output #45                      ; '-'
+    mul rel(-2), #-1, rel(-2)
+std_print_num_pos:
+    lt rel(-2), #10, rel(0)
+    jnz rel(0), #std_print_num_digit
+    push rel(-2)
+    push #10
+    call std_div
+    drop 2
+    push rel(3)                     ; leading digits, n / 10
+    call std_print_num
+    drop 1
+    push rel(-2)
+    push #10
+    call std_mod
+    drop 2
+    add rel(3), #48, rel(0)         ; last digit, n % 10
+    output rel(0)
+    ret
+std_print_num_digit:
+    add rel(-2), #48, rel(0)
+    output rel(0)
+    ret
+
+; std_print_str(s): output the zero terminated string starting at address s
+std_print_str:
+    add rel(-2), #0, std_print_str_load+1
+std_print_str_load:
+    add 0, #0, rel(0)
+    jz rel(0), #std_print_str_done
+    output rel(0)
+    add rel(-2), #1, rel(-2)
+    jmp std_print_str
+std_print_str_done:
+    ret
@@ -0,0 +1,308 @@
+//! Dataflow analysis of recorded executions.
+//!
+//! [`taint`] walks a [`Trace`] and tags every value with the set of
+//! [`Origin`]s it was computed from: the inputs consumed by input
+//! instructions, and optionally the initial contents of memory cells, such as
+//! the noun and verb of day 2. Tags flow through arithmetic, comparisons and
+//! memory, including through addresses: a value read through a tainted
+//! pointer, or by an instruction that was itself written by tainted code,
+//! depends on the taint of the pointer or of the instruction as well.
+//!
+//! The result tells which outputs and which branches depend on which
+//! sources, so there is no need to vary the inputs and watch what changes.
+//!
+//! ```
+//! use intcode::{taint, Origin, Vm};
+//!
+//! // Reads three values, outputs the first plus the third
+//! let mut vm = "3,11,3,12,3,13,1,11,13,11,4,11,99,0,0".parse::<Vm>().unwrap();
+//! vm.start_trace();
+//! while vm.run(vec![1, 2, 3].into_iter(), false).is_ok() {}
+//!
+//! let report = taint(vm.trace().unwrap(), &[]);
+//! let sources = report.outputs[0].sources.iter().collect::<Vec<_>>();
+//! assert_eq!(sources, vec![&Origin::Input(0), &Origin::Input(2)]);
+//! ```
+use super::{Mode, Opcode, Trace};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Where a value came from
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Origin {
+    /// The n-th value consumed by an input instruction, counting from 0
+    Input(usize),
+    /// The contents of a memory cell when the trace started
+    Cell(usize),
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Origin::Input(n) => write!(f, "input {}", n),
+            Origin::Cell(addr) => write!(f, "cell {}", addr),
+        }
+    }
+}
+
+pub type Origins = BTreeSet<Origin>;
+
+/// A value produced by an output instruction
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaintedOutput {
+    pub ip: usize,
+    pub value: isize,
+    pub sources: Origins,
+}
+
+/// Every execution of a conditional jump at one address
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TaintedBranch {
+    pub taken: usize,
+    pub not_taken: usize,
+    /// Everything that any of the decisions depended on
+    pub sources: Origins,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Taint {
+    /// Outputs in the order they were produced
+    pub outputs: Vec<TaintedOutput>,
+    /// Conditional jumps, by address
+    pub branches: BTreeMap<usize, TaintedBranch>,
+}
+
+fn list(sources: &Origins) -> String {
+    if sources.is_empty() {
+        return "nothing".to_string();
+    }
+    let names = sources.iter().map(Origin::to_string).collect::<Vec<_>>();
+    names.join(", ")
+}
+
+/// One line per output, then one per branch
+impl std::fmt::Display for Taint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (n, out) in self.outputs.iter().enumerate() {
+            writeln!(
+                f,
+                "output {} = {} at ip {} depends on {}",
+                n,
+                out.value,
+                out.ip,
+                list(&out.sources)
+            )?;
+        }
+        for (ip, branch) in &self.branches {
+            writeln!(
+                f,
+                "branch at ip {} (taken {}, not taken {}) depends on {}",
+                ip,
+                branch.taken,
+                branch.not_taken,
+                list(&branch.sources)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Shadow memory holding the sources of each tainted cell
+#[derive(Default)]
+struct Shadow {
+    cells: HashMap<usize, Origins>,
+    base: Origins,
+}
+
+impl Shadow {
+    fn cell(&self, addr: usize) -> Origins {
+        self.cells.get(&addr).cloned().unwrap_or_default()
+    }
+
+    /// Where the n-th operand of the instruction at `ip` came from: the
+    /// cell that was read, plus whatever decided which cell that was
+    fn operand(&self, ip: usize, base: usize, n: usize, mode: Mode) -> Origins {
+        let at = ip + 1 + n;
+        let mut sources = self.cell(at);
+        match mode {
+            Mode::Immediate(_) => {}
+            Mode::Position(addr) => sources.extend(self.cell(addr)),
+            Mode::Relative(off) => {
+                sources.extend(self.base.iter().copied());
+                sources.extend(self.cell((base as isize).wrapping_add(off) as usize));
+            }
+        }
+        sources
+    }
+
+    fn write(&mut self, addr: usize, sources: Origins) {
+        if sources.is_empty() {
+            self.cells.remove(&addr);
+        } else {
+            self.cells.insert(addr, sources);
+        }
+    }
+}
+
+/// Run the dataflow analysis over `trace`, counting the initial contents of
+/// `cells` as sources along with every input
+pub fn taint(trace: &Trace, cells: &[usize]) -> Taint {
+    let mut shadow = Shadow::default();
+    for &addr in cells {
+        shadow.write(addr, std::iter::once(Origin::Cell(addr)).collect());
+    }
+    let mut report = Taint::default();
+    let mut inputs = 0;
+
+    for entry in &trace.entries {
+        let params = entry.op.params();
+        // An instruction that was written by tainted code depends on it
+        let mut sources = shadow.cell(entry.ip);
+        // The last parameter of an instruction that writes is the destination
+        let reads = params.len() - entry.write.is_some() as usize;
+        for (n, &mode) in params.iter().enumerate().take(reads) {
+            sources.extend(shadow.operand(entry.ip, entry.base, n, mode));
+        }
+
+        match entry.op {
+            Opcode::Input(_) => {
+                if entry.input.is_none() {
+                    // Starved, so nothing was executed
+                    continue;
+                }
+                sources.insert(Origin::Input(inputs));
+                inputs += 1;
+            }
+            Opcode::Output(_) => report.outputs.push(TaintedOutput {
+                ip: entry.ip,
+                value: entry.output.unwrap_or_default(),
+                sources: sources.clone(),
+            }),
+            Opcode::Jnz(..) | Opcode::Jz(..) => {
+                let condition = entry.operands.first().copied().unwrap_or_default();
+                let taken = matches!(entry.op, Opcode::Jnz(..)) == (condition != 0);
+                let branch = report.branches.entry(entry.ip).or_default();
+                if taken {
+                    branch.taken += 1;
+                } else {
+                    branch.not_taken += 1;
+                }
+                branch.sources.extend(sources.iter().copied());
+            }
+            Opcode::Offset(_) => shadow.base.extend(sources.iter().copied()),
+            _ => {}
+        }
+
+        if let Some((addr, _)) = entry.write {
+            // Which cell was written may depend on a tainted pointer too
+            if let Some(&mode) = params.last() {
+                let at = entry.ip + params.len();
+                sources.extend(shadow.cell(at));
+                if let Mode::Relative(_) = mode {
+                    sources.extend(shadow.base.iter().copied());
+                }
+            }
+            shadow.write(addr, sources);
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assemble, Vm};
+
+    fn analyze(mut vm: Vm, input: &[isize], cells: &[usize]) -> Taint {
+        vm.start_trace();
+        let mut input = input.iter().copied();
+        while vm.run(&mut input, false).is_ok() {}
+        taint(vm.trace().unwrap(), cells)
+    }
+
+    fn sources(list: &[Origin]) -> Origins {
+        list.iter().copied().collect()
+    }
+
+    #[test]
+    fn amplifier() {
+        // Day 7: the phase and the signal both reach the output
+        let vm = "3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0"
+            .parse::<Vm>()
+            .unwrap();
+        let report = analyze(vm, &[4, 0], &[]);
+        assert_eq!(report.outputs.len(), 1);
+        assert_eq!(
+            report.outputs[0].sources,
+            sources(&[Origin::Input(0), Origin::Input(1)])
+        );
+    }
+
+    #[test]
+    fn noun_and_verb() {
+        // Day 2: the noun and verb are addresses, so the result depends on
+        // them through the cells they point at
+        let mut vm = "1,0,0,0,2,0,13,0,4,0,99,0,0,7".parse::<Vm>().unwrap();
+        vm.data[1] = 13;
+        vm.data[2] = 11;
+        let report = analyze(vm, &[], &[1, 2]);
+        assert_eq!(report.outputs[0].value, 49);
+        assert_eq!(
+            report.outputs[0].sources,
+            sources(&[Origin::Cell(1), Origin::Cell(2)])
+        );
+    }
+
+    #[test]
+    fn branches() {
+        let program = assemble(
+            "       input a
+                    input b             ; never used
+                    input n
+             loop:  output #1
+                    add n, #-1, n
+                    jnz n, #loop
+                    lt a, #10, t
+                    jz t, #big
+                    output #0
+             big:   output a
+                    halt
+             a:     data 0
+             b:     data 0
+             n:     data 0
+             t:     data 0",
+        )
+        .unwrap();
+        let report = analyze(Vm::new(program), &[3, 99, 2], &[]);
+        assert!(report.outputs[..3].iter().all(|o| o.sources.is_empty()));
+        assert_eq!(report.outputs[3].sources, sources(&[Origin::Input(0)]));
+
+        let mut branches = report.branches.values();
+        let looped = branches.next().unwrap();
+        assert_eq!((looped.taken, looped.not_taken), (1, 1));
+        assert_eq!(looped.sources, sources(&[Origin::Input(2)]));
+        let compared = branches.next().unwrap();
+        assert_eq!(compared.sources, sources(&[Origin::Input(0)]));
+        assert_eq!(
+            report.to_string().lines().last(),
+            Some("branch at ip 19 (taken 0, not taken 1) depends on input 0")
+        );
+    }
+
+    #[test]
+    fn relative_base() {
+        // The base is moved by an input, so everything read through it
+        // depends on that input
+        let program = assemble(
+            "       input x
+                    offset x
+                    output rel(0)
+                    output #7
+                    halt
+             x:     data 0",
+        )
+        .unwrap();
+        let report = analyze(Vm::new(program), &[2], &[]);
+        assert_eq!(report.outputs[0].sources, sources(&[Origin::Input(0)]));
+        assert!(report.outputs[1].sources.is_empty());
+    }
+}
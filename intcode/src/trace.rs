@@ -0,0 +1,343 @@
+//! Execution traces and deterministic replay.
+//!
+//! Once [`Vm::start_trace`] has been called, every executed instruction is
+//! recorded as a [`TraceEntry`]: where it ran, what it decoded to, the values
+//! of the parameters it read, the cell it wrote, and any input it consumed or
+//! output it produced. A trace can be saved with [`Trace::write_to`] and loaded
+//! again with [`Trace::read_from`], one instruction per line:
+//!
+//! ```text
+//! 0 0 1002,4,3,4 r 33,3 w 4=99 ; mul 4, #3, 4
+//! 4 0 3,9 w 9=5 i 5 ; input 9
+//! 6 0 4,9 r 5 o 5 ; output 9
+//! ```
+//!
+//! The fields are `ip`, `base` and the encoded instruction, followed by the
+//! operands read (`r`), the write (`w`), the input (`i`) and the output (`o`)
+//! when present. Everything after `;` is a comment.
+//!
+//! [`replay`] re-runs a program against the inputs of a recorded trace and
+//! reports the first instruction at which the two executions differ.
+use super::{Error, Opcode, Registry, Step, Vm};
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+
+/// A single executed instruction
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEntry {
+    pub ip: usize,
+    pub op: Opcode,
+    /// Relative base at the time the instruction was executed
+    pub base: usize,
+    /// Values of the parameters that were read, in the order they were read
+    pub operands: Vec<isize>,
+    /// Address and value of the cell that was written
+    pub write: Option<(usize, isize)>,
+    pub input: Option<isize>,
+    pub output: Option<isize>,
+}
+
+/// Every instruction executed since tracing was started
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trace {
+    pub entries: Vec<TraceEntry>,
+    /// Instruction currently being executed
+    current: Option<TraceEntry>,
+}
+
+fn join(values: &[isize]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn invalid(line: usize, msg: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("trace line {}: {}", line, msg),
+    )
+}
+
+/// Renders the entry in the trace file format, comment included
+impl std::fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.ip, self.base, join(&self.op.encode()))?;
+        if !self.operands.is_empty() {
+            write!(f, " r {}", join(&self.operands))?;
+        }
+        if let Some((addr, value)) = self.write {
+            write!(f, " w {}={}", addr, value)?;
+        }
+        if let Some(x) = self.input {
+            write!(f, " i {}", x)?;
+        }
+        if let Some(x) = self.output {
+            write!(f, " o {}", x)?;
+        }
+        write!(f, " ; {}", self.op)
+    }
+}
+
+impl TraceEntry {
+    /// Parse a single line of a trace file. Custom instructions are decoded
+    /// with `registry`
+    fn parse(line: &str, registry: &Arc<Registry>) -> Result<TraceEntry, String> {
+        let line = line.split(';').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let mut next = |what: &str| fields.next().ok_or(format!("missing {}", what));
+        let number = |s: &str| {
+            s.parse::<isize>()
+                .map_err(|_| format!("invalid number {}", s))
+        };
+        let list = |s: &str| s.split(',').map(number).collect::<Result<Vec<_>, _>>();
+
+        let ip = number(next("ip")?)? as usize;
+        let base = number(next("base")?)? as usize;
+        let mut vm = Vm::new(list(next("instruction")?)?);
+        vm.set_registry(registry.clone());
+        let op = vm
+            .decode(0)
+            .map_err(|e| format!("invalid instruction {:?}", e))?;
+
+        let mut entry = TraceEntry {
+            ip,
+            op,
+            base,
+            operands: Vec::new(),
+            write: None,
+            input: None,
+            output: None,
+        };
+        while let Some(tag) = fields.next() {
+            let value = fields.next().ok_or(format!("missing value for {}", tag))?;
+            match tag {
+                "r" => entry.operands = list(value)?,
+                "w" => {
+                    let mut parts = value.splitn(2, '=');
+                    let addr = number(parts.next().unwrap_or_default())?;
+                    let x = number(parts.next().ok_or("missing written value")?)?;
+                    entry.write = Some((addr as usize, x));
+                }
+                "i" => entry.input = Some(number(value)?),
+                "o" => entry.output = Some(number(value)?),
+                _ => return Err(format!("unknown field {}", tag)),
+            }
+        }
+        Ok(entry)
+    }
+}
+
+impl Trace {
+    pub(crate) fn begin(&mut self, ip: usize, op: Opcode, base: usize) {
+        self.current = Some(TraceEntry {
+            ip,
+            op,
+            base,
+            operands: Vec::new(),
+            write: None,
+            input: None,
+            output: None,
+        });
+    }
+
+    pub(crate) fn end(&mut self, result: &Result<Step, Error>) {
+        if let Some(mut entry) = self.current.take() {
+            if let Ok(Step::Output(x)) = result {
+                entry.output = Some(*x);
+            }
+            self.entries.push(entry);
+        }
+    }
+
+    /// Inputs consumed over the whole trace, in order
+    pub fn inputs(&self) -> impl Iterator<Item = isize> + '_ {
+        self.entries.iter().filter_map(|e| e.input)
+    }
+
+    /// Outputs produced over the whole trace, in order
+    pub fn outputs(&self) -> impl Iterator<Item = isize> + '_ {
+        self.entries.iter().filter_map(|e| e.output)
+    }
+
+    pub fn write_to<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        for entry in &self.entries {
+            writeln!(w, "{}", entry)?;
+        }
+        Ok(())
+    }
+
+    /// Load a trace saved with [`Trace::write_to`]. Custom instructions in the
+    /// trace are decoded with `registry`
+    pub fn read_from<R: BufRead>(r: R, registry: &Arc<Registry>) -> std::io::Result<Trace> {
+        let mut trace = Trace::default();
+        for (n, line) in r.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.trim_start().starts_with(';') {
+                continue;
+            }
+            let entry = TraceEntry::parse(&line, registry).map_err(|e| invalid(n + 1, &e))?;
+            trace.entries.push(entry);
+        }
+        Ok(trace)
+    }
+}
+
+/// First point at which a replayed execution departed from the recorded one.
+/// `expected` is `None` if the replay ran for longer than the recording, and
+/// `actual` is `None` if it stopped early
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    /// Number of instructions that matched before the divergence
+    pub index: usize,
+    pub expected: Option<TraceEntry>,
+    pub actual: Option<TraceEntry>,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "executions diverge at instruction {}", self.index)?;
+        match &self.expected {
+            Some(e) => writeln!(f, "  recorded: {}", e)?,
+            None => writeln!(f, "  recorded: (end of trace)")?,
+        }
+        match &self.actual {
+            Some(e) => write!(f, "  replayed: {}", e),
+            None => write!(f, "  replayed: (stopped)"),
+        }
+    }
+}
+
+impl Vm {
+    /// Start recording a trace of every instruction executed from now on,
+    /// discarding any trace recorded so far
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Trace::default());
+    }
+
+    /// Stop tracing, returning the instructions recorded since
+    /// [`Vm::start_trace`]
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    /// Update the entry of the instruction being executed, if tracing
+    pub(crate) fn note<F: FnOnce(&mut TraceEntry)>(&mut self, f: F) {
+        if let Some(entry) = self.trace.as_mut().and_then(|t| t.current.as_mut()) {
+            f(entry);
+        }
+    }
+}
+
+/// Run `vm` until it stops, feeding it the inputs consumed by `recorded`, and
+/// compare each executed instruction against the recording. Returns the first
+/// divergence, or `None` if both executions are identical
+pub fn replay(mut vm: Vm, recorded: &Trace) -> Option<Divergence> {
+    let mut input = recorded.inputs();
+    vm.start_trace();
+    let mut index = 0;
+    loop {
+        let result = vm.step(|| input.next(), false);
+        let trace = vm.trace.as_mut().expect("tracing was started");
+        let actual = trace.entries.pop();
+        let expected = recorded.entries.get(index).cloned();
+        if actual != expected {
+            return Some(Divergence {
+                index,
+                expected,
+                actual,
+            });
+        }
+        index += 1;
+        if result.is_err() {
+            // Both executions stopped here, unless the recording goes on
+            return recorded.entries.get(index).map(|e| Divergence {
+                index,
+                expected: Some(e.clone()),
+                actual: None,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ECHO_SUM: &str = "3,13,3,14,1,13,14,15,4,15,1105,1,0,0,0,0";
+
+    fn record(program: &str, input: &[isize]) -> (Vm, Trace) {
+        let vm = program.parse::<Vm>().unwrap();
+        let mut traced = vm.clone();
+        traced.start_trace();
+        let mut input = input.iter().copied();
+        while traced.run(&mut input, false).is_ok() {}
+        (vm, traced.take_trace().unwrap())
+    }
+
+    #[test]
+    fn recording() {
+        let (_, trace) = record(ECHO_SUM, &[2, 3]);
+        assert_eq!(trace.inputs().collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(trace.outputs().collect::<Vec<_>>(), vec![5]);
+        assert_eq!(
+            trace.entries[2],
+            TraceEntry {
+                ip: 4,
+                op: "1,13,14,15".parse::<Vm>().unwrap().decode(0).unwrap(),
+                base: 0,
+                operands: vec![2, 3],
+                write: Some((15, 5)),
+                input: None,
+                output: None,
+            }
+        );
+        assert_eq!(
+            trace.entries[2].to_string(),
+            "4 0 1,13,14,15 r 2,3 w 15=5 ; add 13, 14, 15"
+        );
+        // The last instruction runs out of input and is recorded as such
+        assert_eq!(trace.entries.len(), 6);
+        assert_eq!(trace.entries[5].op.name(), "input");
+        assert_eq!(trace.entries[5].input, None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let (_, trace) = record(ECHO_SUM, &[-2, 30]);
+        let mut file = Vec::new();
+        trace.write_to(&mut file).unwrap();
+        let loaded = Trace::read_from(&file[..], &Arc::default()).unwrap();
+        assert_eq!(loaded, trace);
+
+        assert!(Trace::read_from(&b"0 0 1,0,0,0 x 1"[..], &Arc::default()).is_err());
+        assert!(Trace::read_from(&b"0 0"[..], &Arc::default()).is_err());
+    }
+
+    #[test]
+    fn replaying() {
+        let (vm, trace) = record(ECHO_SUM, &[4, 5]);
+        assert_eq!(replay(vm.clone(), &trace), None);
+
+        // Multiply instead of adding: the third instruction differs
+        let mut patched = vm;
+        patched.data[4] = 2;
+        let divergence = replay(patched, &trace).unwrap();
+        assert_eq!(divergence.index, 2);
+        assert_eq!(divergence.expected.unwrap().write, Some((15, 9)));
+        assert_eq!(divergence.actual.unwrap().write, Some((15, 20)));
+
+        // A program that halts early
+        let (vm, trace) = record("104,1,104,2,99", &[]);
+        let short = "104,1,99".parse::<Vm>().unwrap();
+        let divergence = replay(short, &trace).unwrap();
+        assert_eq!(divergence.index, 1);
+        assert_eq!(divergence.actual.unwrap().op, Opcode::Halt);
+        assert_eq!(replay(vm, &trace), None);
+    }
+}
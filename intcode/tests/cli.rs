@@ -0,0 +1,204 @@
+//! End-to-end tests of the `intcode` command-line runner
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn program(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("intcode-cli-{}-{}", std::process::id(), name));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+fn intcode(args: &[&str], stdin: &str) -> Output {
+    use std::io::Write;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_intcode"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+fn remove(paths: &[&PathBuf]) {
+    for path in paths {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn halt_and_patch() {
+    // Add [9] + [10] once the operands have been patched in, day 2 style
+    let path = program("patch", "1,0,0,11,4,11,99,0,0,20,22,0\n");
+    let out = intcode(
+        &["--set", "1=9", "--set", "2=10", path.to_str().unwrap()],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "42\n");
+
+    let out = intcode(&["--set", "99999999999=1", path.to_str().unwrap()], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("out of range"));
+    remove(&[&path]);
+}
+
+#[test]
+fn input_sources() {
+    let echo_path = program("echo", "3,7,4,7,1105,1,0,0");
+    let echo = echo_path.to_str().unwrap();
+
+    let out = intcode(&["--input", "1,2, 3", echo], "");
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(stdout(&out), "1\n2\n3\n");
+
+    let out = intcode(&["--input-file", "-", echo], "7\n-8\n");
+    assert_eq!(stdout(&out), "7\n-8\n");
+
+    let file = program("input", "5,6");
+    let out = intcode(&["--input-file", file.to_str().unwrap(), echo], "");
+    assert_eq!(stdout(&out), "5\n6\n");
+
+    // --input comes first, and its last value doesn't run into the next
+    let three = program("three", "3");
+    let args = ["--input", "1,2", "--input-file", three.to_str().unwrap()];
+    let out = intcode(&[&args[..], &[echo]].concat(), "");
+    assert_eq!(stdout(&out), "1\n2\n3\n");
+    let out = intcode(&["--input", "1,2", "--input-file", "-", echo], "9");
+    assert_eq!(stdout(&out), "1\n2\n9\n");
+
+    let out = intcode(&["--ascii-in", "--ascii-out", "--input", "hi\n", echo], "");
+    assert_eq!(stdout(&out), "hi\n");
+    remove(&[&echo_path, &file, &three]);
+}
+
+#[test]
+fn interactive() {
+    use std::io::{BufRead, BufReader, Write};
+    // Prints a prompt, then echoes one value
+    let path = program("prompt", "104,62,3,9,4,9,1105,1,0,0");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_intcode"))
+        .args(["--input-file", "-", path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut reader = BufReader::new(child.stdout.take().unwrap());
+    // The prompt arrives before any input has been sent
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "62\n");
+    stdin.write_all(b"7\n").unwrap();
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "7\n");
+    drop(stdin);
+    assert_eq!(child.wait().unwrap().code(), Some(2));
+
+    let out = intcode(&["--input-file", "-", path.to_str().unwrap()], "1\nx\n");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(stdout(&out), "62\n1\n62\n");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("invalid input value x"));
+    remove(&[&path]);
+}
+
+#[test]
+fn exit_codes() {
+    let fault = program("fault", "204,-1,99");
+    let out = intcode(&[fault.to_str().unwrap()], "");
+    assert_eq!(out.status.code(), Some(3));
+
+    let spin = program("spin", "1105,1,0");
+    let out = intcode(&["--limit", "100", "--profile", spin.to_str().unwrap()], "");
+    assert_eq!(out.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("100 instructions executed"));
+
+    let out = intcode(&["--bogus"], "");
+    assert_eq!(out.status.code(), Some(1));
+
+    let invalid = program("invalid", "# name: typo\n1,0,0,\n0,x9");
+    let out = intcode(&[invalid.to_str().unwrap()], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("line 3, column 3: unexpected 'x9'"));
+    remove(&[&fault, &spin, &invalid]);
+}
+
+#[test]
+fn trace() {
+    let path = program("trace", "1101,1,2,5,99,0");
+    let out = intcode(&["--trace", path.to_str().unwrap()], "");
+    let trace = String::from_utf8_lossy(&out.stderr);
+    assert!(trace.contains("0: add #1, #2, 5"), "{}", trace);
+    assert!(trace.contains("4: halt"), "{}", trace);
+    remove(&[&path]);
+}
+
+#[test]
+fn dump() {
+    let path = program("dump", "3,9,1002,9,2,10,4,10,99,0,0");
+    let text = program("dump.txt", "");
+    let html = program("dump.html", "");
+    for dump in &[&text, &html] {
+        let args = ["--input", "21", "--dump", dump.to_str().unwrap()];
+        let out = intcode(&[&args[..], &[path.to_str().unwrap()]].concat(), "");
+        assert_eq!(stdout(&out), "42\n");
+    }
+    let dumped = std::fs::read_to_string(&text).unwrap();
+    assert!(dumped.contains("mul 9, #2, 10"), "{}", dumped);
+    assert!(dumped.ends_with("9: 0 -> 21\n10: 0 -> 42\n"), "{}", dumped);
+    let page = std::fs::read_to_string(&html).unwrap();
+    assert!(page.starts_with("<!DOCTYPE html>"));
+    assert!(page.contains("title=\"was 0\""));
+    remove(&[&path, &text, &html]);
+}
+
+#[test]
+fn taint() {
+    // Day 2 style: the noun and verb are the addresses of the operands
+    let path = program("taint", "1,9,0,0,4,0,99,0,0,20");
+    let args = ["--taint", "--taint-cell", "1", "--taint-cell", "2"];
+    let out = intcode(&[&args[..], &[path.to_str().unwrap()]].concat(), "");
+    assert_eq!(stdout(&out), "21\n");
+    let report = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        report.contains("output 0 = 21 at ip 4 depends on cell 1, cell 2\n"),
+        "{}",
+        report
+    );
+    remove(&[&path]);
+}
+
+#[test]
+fn record_and_replay() {
+    let sum_path = program("sum", "3,11,3,12,1,11,12,13,4,13,99,0,0,0");
+    let sum = sum_path.to_str().unwrap();
+    let trace_path = program("sum.trace", "");
+    let trace = trace_path.to_str().unwrap();
+
+    let out = intcode(&["--record", trace, "--input", "20,22", sum], "");
+    assert_eq!(stdout(&out), "42\n");
+    let recorded = std::fs::read_to_string(trace).unwrap();
+    assert_eq!(recorded.lines().count(), 5);
+    assert!(recorded.starts_with("0 0 3,11 w 11=20 i 20 ; input 11\n"));
+
+    let out = intcode(&["--replay", trace, sum], "");
+    assert_eq!(out.status.code(), Some(0), "{}", stdout(&out));
+
+    // The same program, multiplying instead
+    let product = program("product", "3,11,3,12,2,11,12,13,4,13,99,0,0,0");
+    let out = intcode(&["--replay", trace, product.to_str().unwrap()], "");
+    assert_eq!(out.status.code(), Some(5));
+    assert!(stdout(&out).contains("diverge at instruction 2"));
+    remove(&[&sum_path, &trace_path, &product]);
+}
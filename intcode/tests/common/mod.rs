@@ -0,0 +1,86 @@
+//! Helpers shared by the integration tests: every execution engine offered by
+//! the `intcode` crate, wrapped behind a single signature so that test cases
+//! can be run against all of them.
+#![allow(dead_code)]
+
+use intcode::{AsyncVm, Error, Executor, Step, Vm};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// Run `vm` until it produces a single output, consuming values from `input`
+pub type Engine = fn(&mut Vm, &mut VecDeque<isize>) -> Result<isize, Error>;
+
+fn engine_run(vm: &mut Vm, input: &mut VecDeque<isize>) -> Result<isize, Error> {
+    vm.run(std::iter::from_fn(|| input.pop_front()), false)
+}
+
+fn engine_run_fn(vm: &mut Vm, input: &mut VecDeque<isize>) -> Result<isize, Error> {
+    vm.run_fn(
+        || input.pop_front().expect("run_fn: input exhausted"),
+        false,
+    )
+}
+
+fn engine_step(vm: &mut Vm, input: &mut VecDeque<isize>) -> Result<isize, Error> {
+    loop {
+        if let Step::Output(x) = vm.step(|| input.pop_front(), false)? {
+            return Ok(x);
+        }
+    }
+}
+
+/// Runs the machine as a future on an [`Executor`]. The input queue is moved
+/// into the task, and handed back along with the machine once it is done
+fn engine_async(vm: &mut Vm, input: &mut VecDeque<isize>) -> Result<isize, Error> {
+    let mut machine = AsyncVm::new(vm.clone());
+    let mut queue = std::mem::take(input);
+    let done = Rc::new(RefCell::new(None));
+    let finished = done.clone();
+    let mut executor = Executor::new();
+    executor.spawn(async move {
+        let result = machine
+            .next_output(&mut std::iter::from_fn(|| queue.pop_front()))
+            .await;
+        *finished.borrow_mut() = Some((machine, queue, result));
+    });
+    // The queue is always ready, so the task can't stall waiting for input
+    assert!(executor.run_until_stalled(), "async: task stalled");
+    let (machine, queue, result) = done.borrow_mut().take().unwrap();
+    *vm = machine.vm;
+    *input = queue;
+    result
+}
+
+pub const ENGINES: &[(&str, Engine)] = &[
+    ("run", engine_run),
+    ("run_fn", engine_run_fn),
+    ("step", engine_step),
+    ("async", engine_async),
+];
+
+/// Observable state of a machine after it stopped executing
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub output: Vec<isize>,
+    pub error: Error,
+    pub vm: Vm,
+}
+
+/// Drive `vm` with `engine` until it stops with an error, collecting every
+/// value that it outputs along the way
+pub fn run_to_end(engine: Engine, mut vm: Vm, input: &[isize]) -> Outcome {
+    let mut input = input.iter().copied().collect::<VecDeque<_>>();
+    let mut output = Vec::new();
+    loop {
+        match engine(&mut vm, &mut input) {
+            Ok(x) => output.push(x),
+            Err(error) => return Outcome { output, error, vm },
+        }
+    }
+}
+
+/// Parse a comma separated program, allowing whitespace and newlines
+pub fn parse(program: &str) -> Vm {
+    program.parse::<Vm>().expect("invalid program")
+}
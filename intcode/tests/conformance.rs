@@ -0,0 +1,354 @@
+//! Conformance suite for the intcode machine, built from the example programs
+//! published alongside the puzzles of days 2, 5, 7 and 9. Every case is run
+//! against every execution engine listed in `common::ENGINES`.
+mod common;
+
+use common::{parse, run_to_end, Engine, ENGINES};
+use intcode::{DecodeMode, Error};
+use std::collections::VecDeque;
+
+struct Case {
+    name: &'static str,
+    program: &'static str,
+    input: &'static [isize],
+    output: &'static [isize],
+    /// Expected memory once the machine halts. Memory that was grown past the
+    /// end of the program must be zeroed unless it is listed here
+    memory: Option<&'static str>,
+}
+
+const QUINE: &str = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+const CMP8: &str = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,\
+                    1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,\
+                    1105,1,46,98,99";
+
+const CASES: &[Case] = &[
+    // Day 2
+    Case {
+        name: "day02: add and multiply",
+        program: "1,9,10,3,2,3,11,0,99,30,40,50",
+        input: &[],
+        output: &[],
+        memory: Some("3500,9,10,70,2,3,11,0,99,30,40,50"),
+    },
+    Case {
+        name: "day02: 1 + 1",
+        program: "1,0,0,0,99",
+        input: &[],
+        output: &[],
+        memory: Some("2,0,0,0,99"),
+    },
+    Case {
+        name: "day02: 3 * 2",
+        program: "2,3,0,3,99",
+        input: &[],
+        output: &[],
+        memory: Some("2,3,0,6,99"),
+    },
+    Case {
+        name: "day02: 99 * 99",
+        program: "2,4,4,5,99,0",
+        input: &[],
+        output: &[],
+        memory: Some("2,4,4,5,99,9801"),
+    },
+    Case {
+        name: "day02: self-modifying halt",
+        program: "1,1,1,4,99,5,6,0,99",
+        input: &[],
+        output: &[],
+        memory: Some("30,1,1,4,2,5,6,0,99"),
+    },
+    // Day 5
+    Case {
+        name: "day05: echo",
+        program: "3,0,4,0,99",
+        input: &[42],
+        output: &[42],
+        memory: Some("42,0,4,0,99"),
+    },
+    Case {
+        name: "day05: immediate multiply",
+        program: "1002,4,3,4,33",
+        input: &[],
+        output: &[],
+        memory: Some("1002,4,3,4,99"),
+    },
+    Case {
+        name: "day05: negative immediate",
+        program: "1101,100,-1,4,0",
+        input: &[],
+        output: &[],
+        memory: Some("1101,100,-1,4,99"),
+    },
+    Case {
+        name: "day05: position eq 8 (true)",
+        program: "3,9,8,9,10,9,4,9,99,-1,8",
+        input: &[8],
+        output: &[1],
+        memory: Some("3,9,8,9,10,9,4,9,99,1,8"),
+    },
+    Case {
+        name: "day05: position eq 8 (false)",
+        program: "3,9,8,9,10,9,4,9,99,-1,8",
+        input: &[7],
+        output: &[0],
+        memory: Some("3,9,8,9,10,9,4,9,99,0,8"),
+    },
+    Case {
+        name: "day05: position lt 8 (true)",
+        program: "3,9,7,9,10,9,4,9,99,-1,8",
+        input: &[5],
+        output: &[1],
+        memory: None,
+    },
+    Case {
+        name: "day05: position lt 8 (false)",
+        program: "3,9,7,9,10,9,4,9,99,-1,8",
+        input: &[8],
+        output: &[0],
+        memory: None,
+    },
+    Case {
+        name: "day05: immediate eq 8 (true)",
+        program: "3,3,1108,-1,8,3,4,3,99",
+        input: &[8],
+        output: &[1],
+        memory: None,
+    },
+    Case {
+        name: "day05: immediate eq 8 (false)",
+        program: "3,3,1108,-1,8,3,4,3,99",
+        input: &[-8],
+        output: &[0],
+        memory: None,
+    },
+    Case {
+        name: "day05: immediate lt 8 (true)",
+        program: "3,3,1107,-1,8,3,4,3,99",
+        input: &[-100],
+        output: &[1],
+        memory: None,
+    },
+    Case {
+        name: "day05: immediate lt 8 (false)",
+        program: "3,3,1107,-1,8,3,4,3,99",
+        input: &[9],
+        output: &[0],
+        memory: None,
+    },
+    Case {
+        name: "day05: position jump (zero)",
+        program: "3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9",
+        input: &[0],
+        output: &[0],
+        memory: None,
+    },
+    Case {
+        name: "day05: position jump (nonzero)",
+        program: "3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9",
+        input: &[3],
+        output: &[1],
+        memory: None,
+    },
+    Case {
+        name: "day05: immediate jump (zero)",
+        program: "3,3,1105,-1,9,1101,0,0,12,4,12,99,1",
+        input: &[0],
+        output: &[0],
+        memory: None,
+    },
+    Case {
+        name: "day05: immediate jump (nonzero)",
+        program: "3,3,1105,-1,9,1101,0,0,12,4,12,99,1",
+        input: &[-1],
+        output: &[1],
+        memory: None,
+    },
+    Case {
+        name: "day05: compare to 8 (below)",
+        program: CMP8,
+        input: &[7],
+        output: &[999],
+        memory: None,
+    },
+    Case {
+        name: "day05: compare to 8 (equal)",
+        program: CMP8,
+        input: &[8],
+        output: &[1000],
+        memory: None,
+    },
+    Case {
+        name: "day05: compare to 8 (above)",
+        program: CMP8,
+        input: &[9],
+        output: &[1001],
+        memory: None,
+    },
+    // Day 9
+    Case {
+        name: "day09: quine",
+        program: QUINE,
+        input: &[],
+        output: &[
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ],
+        memory: None,
+    },
+    Case {
+        name: "day09: 16 digit number",
+        program: "1102,34915192,34915192,7,4,7,99,0",
+        input: &[],
+        output: &[1219070632396864],
+        memory: Some("1102,34915192,34915192,7,4,7,99,1219070632396864"),
+    },
+    Case {
+        name: "day09: large immediate",
+        program: "104,1125899906842624,99",
+        input: &[],
+        output: &[1125899906842624],
+        memory: Some("104,1125899906842624,99"),
+    },
+    Case {
+        name: "day09: relative base",
+        program: "109,2000,109,19,204,-34,99",
+        input: &[],
+        output: &[0],
+        memory: None,
+    },
+];
+
+fn check_memory(name: &str, engine: &str, data: &[isize], expected: &str) {
+    let expected = parse(expected).data;
+    assert!(
+        data.len() >= expected.len(),
+        "{} [{}]: memory shrank to {} cells",
+        name,
+        engine,
+        data.len()
+    );
+    let (head, tail) = data.split_at(expected.len());
+    assert_eq!(head, &expected[..], "{} [{}]: final memory", name, engine);
+    assert!(
+        tail.iter().all(|&x| x == 0),
+        "{} [{}]: unexpected writes to extended memory {:?}",
+        name,
+        engine,
+        tail
+    );
+}
+
+#[test]
+fn golden_programs() {
+    for case in CASES {
+        for &(engine_name, engine) in ENGINES {
+            let out = run_to_end(engine, parse(case.program), case.input);
+            assert_eq!(out.error, Error::Halted, "{} [{}]", case.name, engine_name);
+            assert_eq!(
+                out.output, case.output,
+                "{} [{}]: outputs",
+                case.name, engine_name
+            );
+            if let Some(memory) = case.memory {
+                check_memory(case.name, engine_name, &out.vm.data, memory);
+            }
+        }
+    }
+}
+
+#[test]
+fn golden_programs_are_strict() {
+    // All of the published examples are well-formed, so they must behave the
+    // same when decoded strictly
+    for case in CASES {
+        for &(engine_name, engine) in ENGINES {
+            let mut vm = parse(case.program);
+            vm.set_decode_mode(DecodeMode::Strict);
+            let out = run_to_end(engine, vm, case.input);
+            assert_eq!(out.error, Error::Halted, "{} [{}]", case.name, engine_name);
+            assert_eq!(
+                out.output, case.output,
+                "{} [{}]: outputs",
+                case.name, engine_name
+            );
+        }
+    }
+}
+
+/// Run a chain of five amplifiers, each primed with its phase setting. When
+/// `feedback` is set, the output of the last amplifier is routed back into the
+/// first until the machines halt
+fn amplifiers(engine: Engine, program: &str, phases: &[isize], feedback: bool) -> isize {
+    let mut vms = vec![parse(program); phases.len()];
+    let mut inputs = phases
+        .iter()
+        .map(|&ph| std::iter::once(ph).collect::<VecDeque<_>>())
+        .collect::<Vec<_>>();
+    let mut signal = 0;
+    loop {
+        for (vm, input) in vms.iter_mut().zip(inputs.iter_mut()) {
+            input.push_back(signal);
+            match engine(vm, input) {
+                Ok(x) => signal = x,
+                Err(Error::Halted) => return signal,
+                Err(e) => panic!("amplifier failed: {:?}", e),
+            }
+        }
+        if !feedback {
+            return signal;
+        }
+    }
+}
+
+#[test]
+fn day07_amplifiers() {
+    let cases: &[(&str, &[isize], bool, isize)] = &[
+        (
+            "3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0",
+            &[4, 3, 2, 1, 0],
+            false,
+            43210,
+        ),
+        (
+            "3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0",
+            &[0, 1, 2, 3, 4],
+            false,
+            54321,
+        ),
+        (
+            "3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,\
+             1,32,31,31,4,31,99,0,0,0",
+            &[1, 0, 4, 3, 2],
+            false,
+            65210,
+        ),
+        (
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,\
+             99,0,0,5",
+            &[9, 8, 7, 6, 5],
+            true,
+            139629729,
+        ),
+        (
+            "3,52,1001,52,-5,52,3,53,1,52,56,54,1007,54,5,55,1005,55,26,1001,54,-5,54,\
+             1105,1,12,1,53,54,53,1008,54,0,55,1001,55,1,55,2,53,55,53,4,53,1001,56,-1,56,\
+             1005,56,6,99,0,0,0,0,10",
+            &[9, 7, 8, 5, 6],
+            true,
+            18216,
+        ),
+    ];
+
+    for &(program, phases, feedback, expected) in cases {
+        for &(name, engine) in ENGINES {
+            assert_eq!(
+                amplifiers(engine, program, phases, feedback),
+                expected,
+                "phases {:?} [{}]",
+                phases,
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,386 @@
+//! Differential fuzzing of the intcode execution engines.
+//!
+//! Random, structurally valid programs are generated and executed by every
+//! engine in `common::ENGINES`. All engines must agree on the outputs, the
+//! final memory, `ip`, `base` and the error that stopped the machine, and none
+//! of them may panic.
+//!
+//! Generated programs only ever write to a scratch area placed after the code,
+//! and every backwards jump is the tail of a counted loop, so every program is
+//! guaranteed to terminate. Each program may end in a deliberately faulty
+//! instruction to exercise the error paths.
+//!
+//! The default run is short enough for `cargo test`. For a longer local
+//! fuzzing session, raise the iteration count and pick a fresh seed:
+//!
+//! ```text
+//! INTCODE_FUZZ_ITERS=1000000 INTCODE_FUZZ_SEED=1234 \
+//!     cargo test --release -p intcode --test differential
+//! ```
+mod common;
+
+use common::{run_to_end, Outcome, ENGINES};
+use intcode::Vm;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Number of scratch cells that generated instructions may read and write
+const SCRATCH: usize = 16;
+/// Loop counters live after the scratch area and are only touched by the
+/// loop bookkeeping itself
+const MAX_LOOPS: usize = 16;
+
+/// xorshift64*, so that findings can be reproduced from a seed alone
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn range(&mut self, lo: isize, hi: isize) -> isize {
+        lo + self.below((hi - lo + 1) as usize) as isize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+/// A cell of the program image whose final value depends on where the
+/// scratch area ends up
+enum Fixup {
+    /// Absolute address of a scratch cell
+    Position(usize, usize),
+    /// Scratch cell addressed relative to a known base
+    Relative(usize, usize, isize),
+    /// Absolute address of a loop counter
+    Counter(usize, usize),
+}
+
+struct Generator<'r> {
+    rng: &'r mut Rng,
+    code: Vec<isize>,
+    fixups: Vec<Fixup>,
+    /// Relative base at the current point of the program; only changed at the
+    /// top level, so it is the same on every iteration of a loop
+    base: isize,
+    loops: usize,
+    /// Upper bound on the number of inputs the program can consume
+    inputs: usize,
+}
+
+impl<'r> Generator<'r> {
+    fn new(rng: &'r mut Rng) -> Generator<'r> {
+        Generator {
+            rng,
+            code: Vec::new(),
+            fixups: Vec::new(),
+            base: 0,
+            loops: 0,
+            inputs: 0,
+        }
+    }
+
+    /// Emit a parameter that is read from, returning its mode digit
+    fn read(&mut self) -> isize {
+        match self.rng.below(3) {
+            0 => {
+                let v = self.rng.range(-50, 50);
+                self.code.push(v);
+                1
+            }
+            1 => self.write(),
+            _ => {
+                // Reading the program itself is allowed too
+                let len = self.code.len().max(1);
+                let v = self.rng.below(len) as isize;
+                self.code.push(v);
+                0
+            }
+        }
+    }
+
+    /// Emit a parameter that is written to, returning its mode digit
+    fn write(&mut self) -> isize {
+        let cell = self.rng.below(SCRATCH);
+        let idx = self.code.len();
+        self.code.push(0);
+        if self.rng.chance(50) {
+            self.fixups.push(Fixup::Position(idx, cell));
+            0
+        } else {
+            self.fixups.push(Fixup::Relative(idx, cell, self.base));
+            2
+        }
+    }
+
+    fn instr(&mut self, op: isize, params: &[fn(&mut Generator<'r>) -> isize]) {
+        let at = self.code.len();
+        self.code.push(op);
+        let mut modes = 0;
+        let mut scale = 100;
+        for param in params {
+            modes += param(self) * scale;
+            scale *= 10;
+        }
+        self.code[at] += modes;
+    }
+
+    /// A single instruction that neither jumps nor moves the relative base
+    fn simple(&mut self, multiplicity: usize) {
+        let read = Generator::read as fn(&mut Generator<'r>) -> isize;
+        let write = Generator::write as fn(&mut Generator<'r>) -> isize;
+        match self.rng.below(6) {
+            0 => self.instr(1, &[read, read, write]),
+            1 => self.instr(2, &[read, read, write]),
+            2 => self.instr(7, &[read, read, write]),
+            3 => self.instr(8, &[read, read, write]),
+            4 => {
+                self.inputs += multiplicity;
+                self.instr(3, &[write])
+            }
+            _ => self.instr(4, &[read]),
+        }
+    }
+
+    /// A forward conditional jump over a handful of simple instructions
+    fn branch(&mut self, multiplicity: usize) {
+        let read = Generator::read as fn(&mut Generator<'r>) -> isize;
+        let op = if self.rng.chance(50) { 5 } else { 6 };
+        self.instr(op, &[read]);
+        // The jump target is always an immediate
+        let at = self.code.len() - 2;
+        self.code[at] += 1000;
+        let target = self.code.len();
+        self.code.push(0);
+        for _ in 0..self.rng.range(1, 4) {
+            self.simple(multiplicity);
+        }
+        self.code[target] = self.code.len() as isize;
+    }
+
+    /// A counted loop: `ctr = n; do { body; ctr -= 1 } while ctr != 0`
+    fn repeat(&mut self, depth: usize, multiplicity: usize) {
+        if self.loops == MAX_LOOPS {
+            return self.simple(multiplicity);
+        }
+        let ctr = self.loops;
+        self.loops += 1;
+        let n = self.rng.range(1, 4);
+
+        self.code.extend(&[1101, 0, n, 0]);
+        self.fixups.push(Fixup::Counter(self.code.len() - 1, ctr));
+        let start = self.code.len();
+        self.block(depth + 1, multiplicity * n as usize);
+        self.code.extend(&[1001, 0, -1, 0]);
+        self.fixups.push(Fixup::Counter(self.code.len() - 3, ctr));
+        self.fixups.push(Fixup::Counter(self.code.len() - 1, ctr));
+        self.code.extend(&[1005, 0, start as isize]);
+        self.fixups.push(Fixup::Counter(self.code.len() - 2, ctr));
+    }
+
+    fn block(&mut self, depth: usize, multiplicity: usize) {
+        for _ in 0..self.rng.range(1, 6) {
+            match self.rng.below(10) {
+                0 | 1 if depth < 2 => self.repeat(depth, multiplicity),
+                2 => self.branch(multiplicity),
+                3 if depth == 0 => {
+                    let base = self.rng.range(0, 64);
+                    self.code.extend(&[109, base - self.base]);
+                    self.base = base;
+                }
+                _ => self.simple(multiplicity),
+            }
+        }
+    }
+
+    /// An instruction that is expected to stop the machine with an error
+    fn fault(&mut self) {
+        match self.rng.below(5) {
+            // Relative read below address zero
+            0 => self.code.extend(&[204, -(self.base + 1)]),
+            // Relative base moved below zero
+            1 => self.code.extend(&[109, -(self.base + 1)]),
+            // Immediate write target
+            2 => self.code.extend(&[11101, 1, 2, 3]),
+            // Negative position
+            3 => self.code.extend(&[1, -7, 0, 0]),
+            // Unknown opcode
+            _ => self.code.push(self.rng.range(10, 98) / 10 * 10),
+        }
+    }
+
+    fn finish(mut self) -> (Vec<isize>, Vec<isize>) {
+        if self.rng.chance(25) {
+            self.fault();
+        }
+        self.code.push(99);
+
+        let scratch = self.code.len();
+        let counters = scratch + SCRATCH;
+        for fixup in &self.fixups {
+            match *fixup {
+                Fixup::Position(idx, cell) => self.code[idx] = (scratch + cell) as isize,
+                Fixup::Relative(idx, cell, base) => {
+                    self.code[idx] = (scratch + cell) as isize - base
+                }
+                Fixup::Counter(idx, ctr) => self.code[idx] = (counters + ctr) as isize,
+            }
+        }
+        for _ in 0..SCRATCH {
+            let v = self.rng.range(-20, 20);
+            self.code.push(v);
+        }
+        self.code.resize(self.code.len() + MAX_LOOPS, 0);
+
+        let inputs = (0..self.inputs)
+            .map(|_| self.rng.range(-100, 100))
+            .collect::<Vec<_>>();
+        (self.code, inputs)
+    }
+}
+
+/// Generate a random program along with enough input to satisfy it
+fn generate(rng: &mut Rng) -> (Vec<isize>, Vec<isize>) {
+    let mut gen = Generator::new(rng);
+    gen.block(0, 1);
+    gen.finish()
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn describe(outcome: &Outcome) -> String {
+    format!(
+        "error {:?}, ip {}, base {}, output {:?}",
+        outcome.error, outcome.vm.ip, outcome.vm.base, outcome.output
+    )
+}
+
+/// Run a single program through every engine, returning a description of any
+/// disagreement or panic
+fn check(program: &[isize], input: &[isize]) -> Option<String> {
+    let mut results = Vec::new();
+    for &(name, engine) in ENGINES {
+        let vm = Vm::new(program.to_vec());
+        match panic::catch_unwind(AssertUnwindSafe(|| run_to_end(engine, vm, input))) {
+            Ok(outcome) => results.push((name, outcome)),
+            Err(e) => {
+                let msg = e
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                    .unwrap_or_default();
+                return Some(format!("[{}] panicked: {}", name, msg));
+            }
+        }
+    }
+
+    let (first, expected) = &results[0];
+    for (name, outcome) in &results[1..] {
+        if outcome != expected {
+            let mut msg = format!(
+                "[{}] {}\n[{}] {}",
+                first,
+                describe(expected),
+                name,
+                describe(outcome)
+            );
+            if outcome.vm.data != expected.vm.data {
+                msg.push_str("\nfinal memory differs");
+            }
+            return Some(msg);
+        }
+    }
+    None
+}
+
+#[test]
+fn engines_agree() {
+    let iters = env_or("INTCODE_FUZZ_ITERS", 2000u64);
+    let seed = env_or("INTCODE_FUZZ_SEED", 2019u64);
+
+    let mut findings = Vec::new();
+    for i in 0..iters {
+        let case_seed = seed.wrapping_add(i);
+        let (program, input) = generate(&mut Rng::new(case_seed));
+        if let Some(msg) = check(&program, &input) {
+            findings.push(format!(
+                "seed {}\nprogram {:?}\ninput {:?}\n{}",
+                case_seed, program, input, msg
+            ));
+        }
+    }
+
+    assert!(
+        findings.is_empty(),
+        "{} finding(s):\n\n{}",
+        findings.len(),
+        findings.join("\n\n")
+    );
+}
+
+#[test]
+fn generated_programs_terminate() {
+    // Sanity check the generator itself: programs should do real work and
+    // every one of them must stop
+    let mut rng = Rng::new(7);
+    let mut outputs = 0;
+    for _ in 0..200 {
+        let (program, input) = generate(&mut rng);
+        let outcome = run_to_end(ENGINES[0].1, Vm::new(program), &input);
+        outputs += outcome.output.len();
+    }
+    assert!(outputs > 0);
+}
+
+#[test]
+fn optimizer_preserves_behaviour() {
+    // The optimized program may be laid out differently, so errors that carry
+    // an address are only compared by kind
+    let iters = env_or("INTCODE_FUZZ_ITERS", 2000u64);
+    let seed = env_or("INTCODE_FUZZ_SEED", 2019u64);
+
+    let (mut changed, mut smaller) = (0, 0);
+    for i in 0..iters {
+        let case_seed = seed.wrapping_add(i);
+        let (program, input) = generate(&mut Rng::new(case_seed));
+        let opt = intcode::optimize(&program);
+        let expected = run_to_end(ENGINES[0].1, Vm::new(program.clone()), &input);
+        let actual = run_to_end(ENGINES[0].1, Vm::new(opt.program.clone()), &input);
+        assert!(
+            actual.output == expected.output
+                && std::mem::discriminant(&actual.error) == std::mem::discriminant(&expected.error),
+            "seed {}\nprogram {:?}\noptimized {:?}\ninput {:?}\n{}\n{}",
+            case_seed,
+            program,
+            opt.program,
+            input,
+            describe(&expected),
+            describe(&actual)
+        );
+        changed += (opt.program != program) as usize;
+        smaller += (opt.program.len() < program.len()) as usize;
+    }
+    assert!(
+        changed > 0 && smaller > 0,
+        "{} changed, {} smaller",
+        changed,
+        smaller
+    );
+}
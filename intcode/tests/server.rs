@@ -0,0 +1,120 @@
+//! End-to-end tests of `intcode-server`, talking to it over local sockets
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+
+/// Sums numbers until it reads a zero
+const SUM: &str = "
+    loop:   input x
+            jz x, #done
+            add x, total, total
+            jmp loop
+    done:   output total
+            halt
+    x:      data 0
+    total:  data 0";
+
+/// Stops the server when the test ends, even if it fails
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Start the server, returning it along with the address it listens on
+fn start(name: &str, source: &str, args: &[&str]) -> (Server, String) {
+    let program = intcode::assemble(source).unwrap();
+    let text = program.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    let path = std::env::temp_dir().join(format!("intcode-server-{}-{}", std::process::id(), name));
+    std::fs::write(&path, text.join(",")).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_intcode-server"))
+        .args(args)
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    // The program has been loaded once the server is listening
+    std::fs::remove_file(&path).unwrap();
+    let addr = line.trim().trim_start_matches("listening on ").to_string();
+    (Server(child), addr)
+}
+
+#[test]
+fn concurrent_sessions() {
+    let (_server, addr) = start("sum", SUM, &["--tcp", "127.0.0.1:0"]);
+
+    // Each connection gets a machine of its own, so the sums don't mix
+    let mut a = TcpStream::connect(&addr).unwrap();
+    a.write_all(b"1\n").unwrap();
+    let mut b = TcpStream::connect(&addr).unwrap();
+    b.write_all(b"5, 6\n0\n").unwrap();
+    let mut reply = String::new();
+    b.read_to_string(&mut reply).unwrap();
+    assert_eq!(reply, "11\n");
+
+    a.write_all(b"2\nthree\n0\n").unwrap();
+    let mut reply = String::new();
+    a.read_to_string(&mut reply).unwrap();
+    assert_eq!(reply, "invalid number 'three'\n3\n");
+}
+
+#[test]
+fn session_limit() {
+    // Never touches the connection, so only the limit stops it
+    let spin = "loop: jmp loop";
+    let (_server, addr) = start("spin", spin, &["--tcp", "127.0.0.1:0", "--limit", "1000"]);
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).unwrap();
+    assert_eq!(reply, "");
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket() {
+    use std::os::unix::net::UnixStream;
+
+    // Greets, then echoes a line back
+    let echo = r#"
+            offset #stack
+            push #greeting
+            call std_print_str
+            drop 1
+    loop:   input c
+            output c
+            eq c, #10, t
+            jz t, #loop
+            halt
+    c:      data 0
+    t:      data 0
+    greeting: string "who's there?\n"
+    include std
+    stack:"#;
+    let path = std::env::temp_dir().join(format!("intcode-server-{}.sock", std::process::id()));
+    // Stands in for the socket of a server that was killed
+    std::fs::write(&path, "").unwrap();
+    let (server, addr) = start("echo", echo, &["--ascii", "--unix", path.to_str().unwrap()]);
+    assert_eq!(addr, path.to_str().unwrap());
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "who's there?\n");
+    stream.write_all(b"intcode\n").unwrap();
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "intcode\n");
+
+    drop(server);
+    std::fs::remove_file(&path).unwrap();
+}